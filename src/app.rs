@@ -1,31 +1,93 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use crate::{
     bar,
     components::Spinner,
-    mpd::MpdClient,
-    playback,
+    mpd::{MpdAddress, MpdClient, MpdOptions, Trace, TraceReplay},
+    messages, partitions, playback,
+    profiles::{ConfigFile, Profile, Profiles, ProfilesScreen, DEFAULT_PROFILE},
+    queue::{self, QueueConfig},
     task::{TaskStatus, UseTask},
+    toast::{ToastArea, Toasts},
 };
 use clap::Parser;
+use eyre::WrapErr;
 use iocraft::prelude::*;
 
 /// App configuration, created via command line arguments.
+///
+/// MPD servers are described by named [`Profile`]s from the config file, see [`ConfigFile`].
+/// Connection arguments override settings of the starting profile, and without a config
+/// file they make the only profile.
 #[derive(Debug, Parser, Clone)]
 #[command(version, about)]
 pub struct Config {
-    #[arg(long, default_value_t = String::from("localhost"))]
-    pub host: String,
-    #[arg(long, default_value_t = 8080)]
-    pub port: u16,
+    /// Path to the config file, `$XDG_CONFIG_HOME/minisong/config.toml` by default.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Server profile to start with, instead of `default_profile` from the config file.
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// MPD host: hostname, path to a unix socket, or `@name` of an abstract socket.
+    /// Can be prefixed with `password@`, like `MPD_HOST`.
+    #[arg(long)]
+    pub host: Option<String>,
+    /// MPD port, used only for TCP connections.
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// MPD password, overrides the one from `--host` or `MPD_HOST`.
     #[arg(long)]
     pub password: Option<String>,
+    /// Record every request and response of the MPD protocol to the file.
+    #[arg(long, value_name = "FILE")]
+    pub trace_protocol: Option<PathBuf>,
+    /// Connect to a fake MPD server, which replays the file recorded by `--trace-protocol`.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["profile", "host", "port", "password"])]
+    pub replay_protocol: Option<PathBuf>,
 }
 
-/// Context for the whole app, set in [`Minisong`].
+impl Config {
+    /// Load the config file from `--config`, or the default one if it exists.
+    pub fn config_file(&self) -> eyre::Result<ConfigFile> {
+        match (&self.config, ConfigFile::default_path()) {
+            (Some(path), _) => ConfigFile::load(path),
+            (None, Some(path)) if path.exists() => ConfigFile::load(&path),
+            (None, _) => Ok(ConfigFile::default()),
+        }
+    }
+
+    /// Server profiles from the config `file`, and the index of the starting one.
+    pub fn profiles(&self, file: ConfigFile) -> eyre::Result<(Vec<Profile>, usize)> {
+        let start = self.profile.clone().or_else(|| file.default_profile.clone());
+        let mut profiles = file.into_profiles();
+        if profiles.is_empty() {
+            profiles.push(Profile { name: DEFAULT_PROFILE.to_owned(), ..Default::default() });
+        }
+        let start = match start {
+            Some(name) => profiles
+                .iter()
+                .position(|profile| profile.name == name)
+                .ok_or_else(|| eyre::eyre!("Unknown profile \"{name}\""))?,
+            None => 0,
+        };
+
+        let profile = &mut profiles[start];
+        profile.host = self.host.clone().or(profile.host.take());
+        profile.port = self.port.or(profile.port);
+        profile.password = self.password.clone().or(profile.password.take());
+
+        Ok((profiles, start))
+    }
+}
+
+/// Context for the whole app, set in [`Session`].
 #[derive(Debug)]
 pub struct AppContext {
     pub mpd: MpdClient,
+    pub toasts: Toasts,
+    /// Set while a text input has focus, so app-wide key bindings don't steal typed keys.
+    pub text_input: State<bool>,
+    pub queue_config: Arc<QueueConfig>,
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -34,6 +96,9 @@ pub enum AppTab {
     #[default]
     Playback,
     Queue,
+    Messages,
+    Partitions,
+    Profiles,
 }
 
 impl AppTab {
@@ -41,20 +106,61 @@ impl AppTab {
         match self {
             AppTab::Playback => "Playback",
             AppTab::Queue => "Queue",
+            AppTab::Messages => "Messages",
+            AppTab::Partitions => "Partitions",
+            AppTab::Profiles => "Profiles",
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct RunContext {
-    config: Arc<Config>,
+    profiles: Arc<[Profile]>,
+    start_profile: usize,
+    toasts: Toasts,
+    trace: Option<Arc<Trace>>,
+    queue_config: Arc<QueueConfig>,
 }
 
 /// Main app entry point.
 pub async fn run() -> eyre::Result<()> {
     let config = Config::try_parse()?;
+    let mut file = config.config_file()?;
+    let queue_config = std::mem::take(&mut file.queue);
+    let trace = match &config.trace_protocol {
+        Some(path) => Some(Arc::new(
+            Trace::create(path)
+                .wrap_err_with(|| format!("Creating protocol trace {}", path.display()))?,
+        )),
+        None => None,
+    };
+    // Kept until the app exits, since the fake server stops when it's dropped.
+    let replay = match &config.replay_protocol {
+        Some(path) => Some(
+            TraceReplay::start(path)
+                .wrap_err_with(|| format!("Replaying protocol trace {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let (profiles, start_profile) = match &replay {
+        Some(replay) => {
+            let MpdAddress::Tcp { host, port } = replay.address() else {
+                unreachable!("Replay server listens on TCP");
+            };
+            let name = "replay".to_owned();
+            (vec![Profile { name, host: Some(host), port: Some(port), ..Default::default() }], 0)
+        },
+        None => config.profiles(file)?,
+    };
 
-    element!(ContextProvider(value: Context::owned(RunContext { config: Arc::new(config) })) {
+    let ctx = RunContext {
+        profiles: profiles.into(),
+        start_profile,
+        toasts: Toasts::default(),
+        trace,
+        queue_config: Arc::new(queue_config),
+    };
+    element!(ContextProvider(value: Context::owned(ctx)) {
         Minisong()
     })
     .fullscreen()
@@ -63,16 +169,35 @@ pub async fn run() -> eyre::Result<()> {
     Ok(())
 }
 
-/// Main app UI. It defines app context, app-wide key-bindings and sets terminal window.
+/// Main app UI. It keeps server profiles, and restarts the [`Session`] on profile switch.
 #[component]
 pub fn Minisong(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let ctx = hooks.use_context::<RunContext>().clone();
+    let current = hooks.use_state(|| ctx.start_profile);
+    let session = hooks.use_state(|| 0);
+    let profiles = Profiles { list: ctx.profiles.clone(), current, session };
+
+    element! {
+        ContextProvider(value: Context::owned(profiles)) {
+            Session(key: session.get())
+        }
+    }
+}
+
+/// Connection to the current server profile. It defines app context, app-wide key-bindings
+/// and sets terminal window.
+#[component]
+fn Session(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
     let (width, height) = hooks.use_terminal_size();
     let mut system = hooks.use_context_mut::<SystemContext>();
 
     let mut should_exit = hooks.use_state(|| false);
+    let text_input = hooks.use_state(|| false);
     hooks.use_terminal_events({
         move |event| match event {
-            TerminalEvent::Key(KeyEvent { code, kind, .. }) if kind != KeyEventKind::Release => {
+            TerminalEvent::Key(KeyEvent { code, kind, .. })
+                if kind != KeyEventKind::Release && !text_input.get() =>
+            {
                 match code {
                     KeyCode::Char('q') => should_exit.set(true),
                     _ => {},
@@ -85,23 +210,50 @@ pub fn Minisong(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
         system.exit();
     }
 
-    let ctx = hooks.use_context::<RunContext>().clone();
-    let client_task = hooks.use_task(move || {
-        MpdClient::new((ctx.config.host.clone(), ctx.config.port), ctx.config.password.clone())
+    let RunContext { toasts, trace, queue_config, .. } = hooks.use_context::<RunContext>().clone();
+    let profile = hooks.use_context::<Profiles>().current().clone();
+    let client_task = hooks.use_task(async move {
+        let (addr, password) = profile.mpd_address()?;
+        let timeouts = profile.timeouts()?;
+        let mpd = MpdClient::with_options(MpdOptions { addr, password, timeouts, trace }).await?;
+        if let Some(partition) = &profile.partition {
+            // Connection tasks of the client keep running until it's closed.
+            if let Err(error) = mpd.switch_partition(partition).await {
+                mpd.close();
+                return Err(error.into());
+            }
+        }
+        Ok(mpd)
     });
 
     let status = client_task.status();
     match &*status {
-        TaskStatus::Error(err) => panic!("Connecting to MPD: {}", err),
-        TaskStatus::Done(mpd) => element! {
-            ContextProvider(value: Context::owned(AppContext { mpd: mpd.clone() })) {
-                View(width, height, flex_direction: FlexDirection::Column) {
-                    bar::PlayerStatusBar()
-                    AppTabs()
-                }
+        TaskStatus::Error(err) => element! {
+            View(
+                width, height,
+                gap: 1,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column
+            ) {
+                Text(color: Color::Red, content: format!("Connecting to MPD: {err}"))
+                ProfilesScreen()
             }
         }
         .into_any(),
+        TaskStatus::Done(mpd) => {
+            let app_ctx = AppContext { mpd: mpd.clone(), toasts, text_input, queue_config };
+            element! {
+                ContextProvider(value: Context::owned(app_ctx)) {
+                    View(width, height, flex_direction: FlexDirection::Column) {
+                        bar::PlayerStatusBar()
+                        AppTabs()
+                        ToastArea()
+                    }
+                }
+            }
+            .into_any()
+        },
         TaskStatus::InProgress => element! {
             View(
                 width, height,
@@ -120,10 +272,13 @@ pub fn Minisong(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
 
 #[component]
 fn AppTabs(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let text_input = hooks.use_context::<AppContext>().text_input;
     let mut tab = hooks.use_state_default::<AppTab>();
     hooks.use_terminal_events({
         move |event| match event {
-            TerminalEvent::Key(KeyEvent { code, kind, .. }) if kind != KeyEventKind::Release => {
+            TerminalEvent::Key(KeyEvent { code, kind, .. })
+                if kind != KeyEventKind::Release && !text_input.get() =>
+            {
                 match code {
                     KeyCode::Char('1') => {
                         tab.set(AppTab::Playback);
@@ -131,6 +286,15 @@ fn AppTabs(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
                     KeyCode::Char('2') => {
                         tab.set(AppTab::Queue);
                     },
+                    KeyCode::Char('3') => {
+                        tab.set(AppTab::Messages);
+                    },
+                    KeyCode::Char('4') => {
+                        tab.set(AppTab::Partitions);
+                    },
+                    KeyCode::Char('5') => {
+                        tab.set(AppTab::Profiles);
+                    },
                     _ => {},
                 }
             },
@@ -148,15 +312,10 @@ fn AppTabs(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
             ) {
                 #(match tab.get() {
                     AppTab::Playback => element! { playback::PlaybackScreen() }.into_any(),
-                    AppTab::Queue => element! {
-                        View(
-                            width: Percent(100.0),
-                            align_items: AlignItems::Center,
-                            justify_content: JustifyContent::Center,
-                        ) {
-                            Text(content: "TODO: Queue Tab")
-                        }
-                    }.into_any(),
+                    AppTab::Queue => element! { queue::QueueScreen() }.into_any(),
+                    AppTab::Messages => element! { messages::MessagesScreen() }.into_any(),
+                    AppTab::Partitions => element! { partitions::PartitionsScreen() }.into_any(),
+                    AppTab::Profiles => element! { ProfilesScreen() }.into_any(),
                 })
             }
             View(
@@ -171,6 +330,9 @@ fn AppTabs(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
             ) {
                 SelectableTab(current_tab: tab, self_tab: AppTab::Playback)
                 SelectableTab(current_tab: tab, self_tab: AppTab::Queue)
+                SelectableTab(current_tab: tab, self_tab: AppTab::Messages)
+                SelectableTab(current_tab: tab, self_tab: AppTab::Partitions)
+                SelectableTab(current_tab: tab, self_tab: AppTab::Profiles)
             }
         }
     }
@@ -183,7 +345,8 @@ struct SelectableTabProps {
 }
 
 #[component]
-fn SelectableTab(_hooks: Hooks, props: &SelectableTabProps) -> impl Into<AnyElement<'static>> {
+fn SelectableTab(mut hooks: Hooks, props: &SelectableTabProps) -> impl Into<AnyElement<'static>> {
+    let mut text_input = hooks.use_context::<AppContext>().text_input;
     let is_selected = props.current_tab.is_some_and(|tab| tab == props.self_tab);
     let color = if is_selected { Color::White } else { Color::Grey };
 
@@ -194,6 +357,10 @@ fn SelectableTab(_hooks: Hooks, props: &SelectableTabProps) -> impl Into<AnyElem
             return;
         };
 
+        // Input of the left screen is gone with it, so keys are for the app again.
+        if current_tab.get() != self_tab {
+            text_input.set(false);
+        }
         current_tab.set(self_tab);
     };
 
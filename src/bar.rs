@@ -1,15 +1,18 @@
 use iocraft::prelude::*;
 
-use crate::app::AppContext;
+use crate::{
+    app::AppContext,
+    mpd::{ConnectionState, Feature, Single, Subsystem},
+};
 
 /// Global MPD status state for [`PlayerStatusBar`].
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 struct PlayerStatus {
     volume: f32,
     queue: (usize, usize),
     repeat: bool,
     random: bool,
-    single: bool,
+    single: Single,
     consume: bool,
 }
 
@@ -19,60 +22,115 @@ enum Action {
     ChangeVolume(f32),
     ToggleRepeat,
     ToggleRandom,
+    /// Cycle through single modes, including `oneshot` if the server supports it.
     ToggleSingle,
     ToggleConsume,
 }
 
+impl Action {
+    /// MPD command used by the action, to check if it's allowed.
+    fn command(&self) -> &'static str {
+        match self {
+            Action::ChangeVolume(_) => "setvol",
+            Action::ToggleRepeat => "repeat",
+            Action::ToggleRandom => "random",
+            Action::ToggleSingle => "single",
+            Action::ToggleConsume => "consume",
+        }
+    }
+}
+
 #[component]
 pub fn PlayerStatusBar(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
     let ctx = hooks.use_context::<AppContext>();
 
     let mut mpd_status: State<PlayerStatus> = hooks.use_state_default();
-    let mut mpd = ctx.mpd.clone();
+    let mut connection: State<ConnectionState> = hooks.use_state_default();
+    let mut partition: State<Option<String>> = hooks.use_state_default();
+    let mpd = ctx.mpd.clone();
     hooks.use_future(async move {
         loop {
-            {
-                let mut client = mpd.bind().await;
-                let status = client.status().unwrap();
-                mpd_status.set(PlayerStatus {
-                    volume: status.volume as f32 / 100.0,
-                    queue: (
-                        status.song.map(|song| song.pos as usize + 1).unwrap_or_default(),
-                        status.queue_len as usize,
-                    ),
-                    repeat: status.repeat,
-                    random: status.random,
-                    single: status.single,
-                    consume: status.consume,
-                });
+            connection.set(mpd.connection_state());
+            if let Ok(mut client) = mpd.bind().await {
+                match client.status().await {
+                    Ok(status) => {
+                        mpd_status.set(PlayerStatus {
+                            volume: status.volume.unwrap_or_default() as f32 / 100.0,
+                            queue: (
+                                status.song.map(|song| song.pos as usize + 1).unwrap_or_default(),
+                                status.queue_len as usize,
+                            ),
+                            repeat: status.repeat,
+                            random: status.random,
+                            single: status.single,
+                            consume: status.consume,
+                        });
+                        partition.set(status.partition);
+                    },
+                    Err(error) => mpd.handle_error(&error),
+                }
             }
 
-            mpd.wait_for_update().await;
+            mpd.wait_for(&[
+                Subsystem::Mixer,
+                Subsystem::Options,
+                Subsystem::Playlist,
+                Subsystem::Player,
+                Subsystem::Partition,
+            ])
+            .await;
         }
     });
 
     let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
     let action = hooks.use_async_handler(move |action: Action| {
-        let mut mpd = mpd.clone();
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
         async move {
-            let mut client = mpd.bind_then_notify().await;
-            match action {
+            if let Some(reason) = mpd.capabilities().unavailable_reason(action.command()) {
+                toasts.error(format!("{}: {reason}", action.command()));
+                return;
+            }
+            let Ok(mut client) = mpd.bind_then_notify().await else {
+                return;
+            };
+            let status = *mpd_status.read();
+            let result = match action {
                 Action::ChangeVolume(amount) => {
-                    client
-                        .volume(
-                            ((mpd_status.read().volume + amount) * 100.0).clamp(0.0, i8::MAX as f32)
-                                as i8,
-                        )
-                        .unwrap();
+                    let volume = ((status.volume + amount) * 100.0).clamp(0.0, 100.0);
+                    client.set_volume(volume.round() as u8).await
                 },
-                Action::ToggleRepeat => client.repeat(!mpd_status.read().repeat).unwrap(),
-                Action::ToggleRandom => client.random(!mpd_status.read().random).unwrap(),
-                Action::ToggleSingle => client.single(!mpd_status.read().single).unwrap(),
-                Action::ToggleConsume => client.consume(!mpd_status.read().consume).unwrap(),
+                Action::ToggleRepeat => client.repeat(!status.repeat).await,
+                Action::ToggleRandom => client.random(!status.random).await,
+                Action::ToggleSingle => {
+                    let single = match status.single {
+                        Single::Off => Single::On,
+                        Single::On if mpd.capabilities().supports(Feature::SingleOneshot) => {
+                            Single::Oneshot
+                        },
+                        Single::On | Single::Oneshot => Single::Off,
+                    };
+                    client.single(single).await
+                },
+                Action::ToggleConsume => client.consume(!status.consume).await,
+            };
+            if let Err(error) = result {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
             }
         }
     });
 
+    let capabilities = ctx.mpd.capabilities();
+    let color_for = |action: Action| {
+        if capabilities.has_command(action.command()) {
+            None
+        } else {
+            Some(Color::DarkGrey)
+        }
+    };
+
     element! {
         View(
             width: Percent(100.0),
@@ -83,33 +141,99 @@ pub fn PlayerStatusBar(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
             align_items: AlignItems::Center,
             justify_content: JustifyContent::SpaceBetween,
         ) {
-            VolumeSlider(handler: action.clone(), volume: mpd_status.read().volume)
+            View(
+                flex_direction: FlexDirection::Row,
+                gap: 1,
+            ) {
+                ConnectionIndicator(state: connection.get())
+                VolumeSlider(
+                    handler: action.clone(),
+                    volume: mpd_status.read().volume,
+                    color: color_for(Action::ChangeVolume(0.0)),
+                )
+                #(partition.read().as_ref().map(|partition| element! {
+                    Text(color: Color::Blue, content: format!("⌂ {partition}"))
+                }))
+            }
             Text(content: format!("≡ {} / {}", mpd_status.read().queue.0, mpd_status.read().queue.1))
             View(
                 flex_direction: FlexDirection::Row,
                 gap: 1,
             ) {
                 Button(handler: action.bind(Action::ToggleRepeat)) {
-                    Text(content: if mpd_status.read().repeat { "🔁" } else { "·" })
+                    Text(
+                        color: color_for(Action::ToggleRepeat),
+                        content: if mpd_status.read().repeat { "🔁" } else { "·" },
+                    )
                 }
                 Button(handler: action.bind(Action::ToggleRandom)) {
-                    Text(content: if mpd_status.read().random { "🔀" } else { "·" })
+                    Text(
+                        color: color_for(Action::ToggleRandom),
+                        content: if mpd_status.read().random { "🔀" } else { "·" },
+                    )
                 }
                 Button(handler: action.bind(Action::ToggleSingle)) {
-                    Text(content: if mpd_status.read().single { "🔂" } else { "·" })
+                    Text(
+                        color: color_for(Action::ToggleSingle),
+                        content: match mpd_status.read().single {
+                            Single::Off => "·",
+                            Single::On => "🔂",
+                            Single::Oneshot => "🔂¹",
+                        },
+                    )
                 }
                 Button(handler: action.bind(Action::ToggleConsume)) {
-                    Text(content: if mpd_status.read().consume { "🗑️" } else { "·" })
+                    Text(
+                        color: color_for(Action::ToggleConsume),
+                        content: if mpd_status.read().consume { "🗑️" } else { "·" },
+                    )
                 }
             }
         }
     }
 }
 
+#[derive(Default, Props)]
+struct ConnectionIndicatorProps {
+    state: ConnectionState,
+}
+
+/// Shows the state of the MPD connection, and allows to retry after it has failed.
+#[component]
+fn ConnectionIndicator(
+    hooks: Hooks,
+    props: &ConnectionIndicatorProps,
+) -> impl Into<AnyElement<'static>> {
+    let ctx = hooks.use_context::<AppContext>();
+    let mpd = ctx.mpd.clone();
+
+    match props.state {
+        ConnectionState::Connected => element! {
+            Text(color: Color::Green, content: "●")
+        }
+        .into_any(),
+        ConnectionState::Reconnecting(attempt) => element! {
+            Text(color: Color::Yellow, content: format!("● Reconnecting ({attempt})"))
+        }
+        .into_any(),
+        ConnectionState::Failed => element! {
+            Button(handler: move |_| mpd.reconnect()) {
+                Text(color: Color::Red, content: "● Disconnected, click to retry")
+            }
+        }
+        .into_any(),
+        ConnectionState::Closed => element! {
+            Text(color: Color::DarkGrey, content: "● Closed")
+        }
+        .into_any(),
+    }
+}
+
 #[derive(Default, Props)]
 struct VolumeSliderProps {
     handler: RefHandler<Action>,
     volume: f32,
+    color: Option<Color>,
 }
 
 #[component]
@@ -127,7 +251,7 @@ fn VolumeSlider(mut hooks: Hooks, props: &VolumeSliderProps) -> impl Into<AnyEle
     });
 
     element! {
-        Text(content: {
+        Text(color: props.color, content: {
             let volume = props.volume * 100.0;
             let icon = if volume <= 33.0 {
                 "🔈"
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use iocraft::prelude::*;
 
 const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
@@ -24,6 +26,8 @@ pub fn Spinner(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
 pub struct ProgressBarProps {
     pub amount: f32,
     pub handler: Handler<'static, f32>,
+    /// Color of the elapsed part, magenta by default.
+    pub color: Option<Color>,
 }
 
 #[component]
@@ -53,8 +57,98 @@ pub fn ProgressBar<'a>(
                 Text(content: "·".repeat(full_width as usize), weight: Weight::Light)
             }
             View(width: Percent(100.0 * props.amount), position: Position::Absolute) {
-                Text(content: "—".repeat(full_width as usize), color: Color::Magenta)
+                Text(
+                    content: "—".repeat(full_width as usize),
+                    color: props.color.unwrap_or(Color::Magenta),
+                )
+            }
+        }
+    }
+}
+
+/// Renders a row of [`VirtualList`] by its index, or gives `None` if it isn't loaded yet.
+#[derive(Default)]
+pub struct RowRenderer(Option<Box<dyn Fn(usize) -> Option<AnyElement<'static>> + Send + Sync>>);
+
+impl<F> From<F> for RowRenderer
+where
+    F: Fn(usize) -> Option<AnyElement<'static>> + Send + Sync + 'static,
+{
+    fn from(render: F) -> Self {
+        Self(Some(Box::new(render)))
+    }
+}
+
+#[derive(Default, Props)]
+pub struct VirtualListProps {
+    /// Number of rows in the list.
+    pub len: usize,
+    /// Row which is kept in view, usually the one under the cursor.
+    pub cursor: usize,
+    pub render_row: RowRenderer,
+    /// Called with rows in view which aren't loaded yet, whenever they change.
+    pub on_missing: Handler<'static, Range<usize>>,
+    /// Called with rows in view whenever they change, like after scrolling or resizing.
+    pub on_scroll: Handler<'static, Range<usize>>,
+}
+
+/// List which renders only rows in view, so it stays smooth with any number of them.
+/// Rows which aren't loaded yet are shown as placeholders, and reported with `on_missing`
+/// to be loaded lazily.
+#[component]
+pub fn VirtualList(
+    mut hooks: Hooks,
+    props: &mut VirtualListProps,
+) -> impl Into<AnyElement<'static>> {
+    // Rows fitting in the list, known after the first render.
+    let rect = hooks.use_component_rect().get().unwrap_or_default();
+    let rows = (rect.bottom - rect.top).max(1) as usize;
+    // First row in view.
+    let mut offset = hooks.use_state(|| 0usize);
+
+    // Scroll only as much as needed to keep the cursor in view.
+    let cursor = props.cursor.min(props.len.saturating_sub(1));
+    let top = offset.get().min(props.len.saturating_sub(rows));
+    let top = top.min(cursor).max((cursor + 1).saturating_sub(rows));
+    if top != offset.get() {
+        offset.set(top);
+    }
+    let view = top..(top + rows).min(props.len);
+
+    let mut missing: Option<Range<usize>> = None;
+    let render = props.render_row.0.as_ref();
+    let elements: Vec<AnyElement<'static>> = view
+        .clone()
+        .map(|index| {
+            let row = render.and_then(|render| render(index)).unwrap_or_else(|| {
+                missing.get_or_insert(index..index).end = index + 1;
+                element! { Text(content: "…", weight: Weight::Light) }.into_any()
+            });
+            element! {
+                View(key: index, height: 1, flex_shrink: 0.0, overflow: Overflow::Hidden) {
+                    #(row)
+                }
             }
+            .into_any()
+        })
+        .collect();
+
+    // Handlers change the state of the parent, so they're called after the render.
+    let mut on_scroll = props.on_scroll.take();
+    let shown = view.clone();
+    hooks.use_effect(move || on_scroll(shown), view);
+    let mut on_missing = props.on_missing.take();
+    let reported = missing.clone();
+    hooks.use_effect(move || reported.into_iter().for_each(|rows| on_missing(rows)), missing);
+
+    element! {
+        View(
+            width: Percent(100.0),
+            height: Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            overflow: Overflow::Hidden,
+        ) {
+            #(elements)
         }
     }
 }
@@ -67,18 +161,15 @@ pub struct DurationProps {
 
 #[component]
 pub fn Duration(props: &DurationProps) -> impl Into<AnyElement<'static>> {
-    match (props.duration.num_hours(), props.duration.num_minutes(), props.duration.num_seconds()) {
-        (0, 0, seconds) => {
-            element! { Text(weight: props.weight, content: format!("00:{seconds:-02}")) }
-        },
-        (0, minutes, seconds) => {
-            let seconds = seconds - minutes * 60;
-            element! { Text(weight: props.weight, content: format!("{minutes:-02}:{seconds:-02}")) }
-        },
-        (hours, minutes, seconds) => {
-            let minutes = minutes - hours * 60;
-            let seconds = seconds - minutes * 60;
-            element! { Text(weight: props.weight, content: format!("{hours:-02}:{minutes:-02}:{seconds:-02}")) }
-        },
+    element! { Text(weight: props.weight, content: format_duration(props.duration)) }
+}
+
+/// Format the `duration` as `mm:ss`, or `hh:mm:ss` if it takes hours.
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let (hours, minutes, seconds) =
+        (duration.num_hours(), duration.num_minutes() % 60, duration.num_seconds() % 60);
+    match hours {
+        0 => format!("{minutes:-02}:{seconds:-02}"),
+        hours => format!("{hours:-02}:{minutes:-02}:{seconds:-02}"),
     }
 }
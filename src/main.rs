@@ -6,9 +6,14 @@ use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt};
 mod app;
 mod bar;
 mod components;
+mod messages;
 mod mpd;
+mod partitions;
 mod playback;
+mod profiles;
+mod queue;
 mod task;
+mod toast;
 
 static PANIC: OnceLock<String> = OnceLock::new();
 
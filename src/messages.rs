@@ -0,0 +1,256 @@
+use iocraft::prelude::*;
+
+use crate::{
+    app::AppContext,
+    components::VirtualList,
+    mpd::{Message, Subsystem},
+};
+
+/// Channels and received messages for [`MessagesScreen`].
+#[derive(Debug, Default, Clone)]
+struct Mailbox {
+    /// Channels with subscribers, including ours, sorted by name.
+    channels: Vec<String>,
+    subscriptions: Vec<String>,
+    messages: Vec<Message>,
+}
+
+/// Text being typed on [`MessagesScreen`].
+#[derive(Debug, Clone)]
+enum Input {
+    /// Name of a channel to subscribe to.
+    Channel(String),
+    /// Message for the selected channel.
+    Message(String),
+}
+
+impl Input {
+    fn text(&self) -> &str {
+        match self {
+            Input::Channel(text) | Input::Message(text) => text,
+        }
+    }
+}
+
+/// Actions for [`MessagesScreen`].
+#[derive(Debug, Clone)]
+enum Action {
+    Subscribe(String),
+    Unsubscribe(String),
+    Send { channel: String, text: String },
+}
+
+impl Action {
+    /// MPD command used by the action, to check if it's allowed.
+    fn command(&self) -> &'static str {
+        match self {
+            Action::Subscribe(_) => "subscribe",
+            Action::Unsubscribe(_) => "unsubscribe",
+            Action::Send { .. } => "sendmessage",
+        }
+    }
+}
+
+/// Client-to-client messages: subscribe to channels, read and send messages.
+#[component]
+pub fn MessagesScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let ctx = hooks.use_context::<AppContext>();
+
+    let mut mailbox: State<Mailbox> = hooks.use_state_default();
+    let mpd = ctx.mpd.clone();
+    hooks.use_future(async move {
+        loop {
+            if let Ok(mut client) = mpd.bind().await {
+                match client.channels().await {
+                    Ok(mut channels) => {
+                        let subscriptions = mpd.subscriptions();
+                        channels.extend(subscriptions.iter().cloned());
+                        channels.sort();
+                        channels.dedup();
+                        mailbox.set(Mailbox { channels, subscriptions, messages: mpd.messages() });
+                    },
+                    Err(error) => mpd.handle_error(&error),
+                }
+            }
+
+            mpd.wait_for(&[Subsystem::Message, Subsystem::Subscription]).await;
+        }
+    });
+
+    let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
+    let action = hooks.use_async_handler(move |action: Action| {
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
+        async move {
+            if let Some(reason) = mpd.capabilities().unavailable_reason(action.command()) {
+                toasts.error(format!("{}: {reason}", action.command()));
+                return;
+            }
+            let result = match &action {
+                Action::Subscribe(channel) => mpd.subscribe(channel).await,
+                Action::Unsubscribe(channel) => mpd.unsubscribe(channel).await,
+                Action::Send { channel, text } => match mpd.bind().await {
+                    Ok(mut client) => client.send_message(channel, text).await,
+                    Err(error) => Err(error),
+                },
+            };
+            if let Err(error) = result {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
+            }
+        }
+    });
+
+    let mut cursor = hooks.use_state(|| 0usize);
+    let mut input: State<Option<Input>> = hooks.use_state_default();
+    let mut text_input = ctx.text_input;
+    hooks.use_terminal_events(move |event| match event {
+        TerminalEvent::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) => {
+            let selected = mailbox.read().channels.get(cursor.get()).cloned();
+            if input.read().is_some() {
+                match code {
+                    KeyCode::Enter => {
+                        let typed = input.write().take();
+                        text_input.set(false);
+                        match typed {
+                            Some(Input::Channel(channel)) if !channel.trim().is_empty() => {
+                                (action)(Action::Subscribe(channel.trim().to_owned()));
+                            },
+                            Some(Input::Message(text)) if !text.is_empty() => {
+                                if let Some(channel) = selected {
+                                    (action)(Action::Send { channel, text });
+                                }
+                            },
+                            _ => {},
+                        }
+                    },
+                    KeyCode::Esc => {
+                        input.set(None);
+                        text_input.set(false);
+                    },
+                    _ => {},
+                }
+                return;
+            }
+
+            match code {
+                KeyCode::Up | KeyCode::Char('k') => cursor.set(cursor.get().saturating_sub(1)),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    let len = mailbox.read().channels.len();
+                    cursor.set((cursor.get() + 1).min(len.saturating_sub(1)));
+                },
+                KeyCode::Char('n') => {
+                    input.set(Some(Input::Channel(String::new())));
+                    text_input.set(true);
+                },
+                KeyCode::Char('s') => {
+                    if let Some(channel) = selected {
+                        if mailbox.read().subscriptions.contains(&channel) {
+                            (action)(Action::Unsubscribe(channel));
+                        } else {
+                            (action)(Action::Subscribe(channel));
+                        }
+                    }
+                },
+                KeyCode::Enter | KeyCode::Char('i') if selected.is_some() => {
+                    input.set(Some(Input::Message(String::new())));
+                    text_input.set(true);
+                },
+                _ => {},
+            }
+        },
+        _ => {},
+    });
+
+    // Messages pile up for the whole session, so only the ones in view are rendered.
+    let render_channel = move |index: usize| {
+        let mailbox = mailbox.read();
+        let channel = mailbox.channels.get(index)?;
+        let is_subscribed = mailbox.subscriptions.contains(channel);
+        let color = match (index == cursor.get(), is_subscribed) {
+            (true, _) => Color::White,
+            (false, true) => Color::Blue,
+            (false, false) => Color::Grey,
+        };
+        let content = format!("{} {channel}", if is_subscribed { "●" } else { "○" });
+        Some(element! { Text(color, content) }.into_any())
+    };
+    let render_message = move |index: usize| {
+        let mailbox = mailbox.read();
+        let message = mailbox.messages.get(index)?;
+        let row = element! {
+            View(flex_direction: FlexDirection::Row, gap: 1) {
+                Text(color: Color::Blue, content: format!("[{}]", message.channel))
+                Text(content: message.text.clone())
+            }
+        };
+        Some(row.into_any())
+    };
+
+    let mailbox = mailbox.read();
+    let selected = mailbox.channels.get(cursor.get());
+
+    element! {
+        View(
+            width: Percent(100.0),
+            height: Percent(100.0),
+            flex_direction: FlexDirection::Column,
+        ) {
+            View(flex_grow: 1.0, flex_direction: FlexDirection::Row, gap: 4) {
+                View(flex_direction: FlexDirection::Column) {
+                    Text(weight: Weight::Bold, content: "Channels")
+                    View(flex_grow: 1.0) {
+                        VirtualList(
+                            len: mailbox.channels.len(),
+                            cursor: cursor.get(),
+                            render_row: render_channel,
+                        )
+                    }
+                }
+                // Newest messages are kept in view, at the bottom.
+                View(flex_grow: 1.0) {
+                    VirtualList(
+                        len: mailbox.messages.len(),
+                        cursor: mailbox.messages.len().saturating_sub(1),
+                        render_row: render_message,
+                    )
+                }
+            }
+            #(match &*input.read() {
+                Some(typed) => {
+                    let prompt = match typed {
+                        Input::Channel(_) => "Subscribe to:".to_owned(),
+                        Input::Message(_) => {
+                            format!("To {}:", selected.map(String::as_str).unwrap_or_default())
+                        },
+                    };
+                    element! {
+                        View(flex_direction: FlexDirection::Row, gap: 1) {
+                            Text(weight: Weight::Bold, content: prompt)
+                            TextInput(
+                                has_focus: true,
+                                value: typed.text().to_owned(),
+                                on_change: move |value| {
+                                    let typed = match &*input.read() {
+                                        Some(Input::Channel(_)) => Input::Channel(value),
+                                        _ => Input::Message(value),
+                                    };
+                                    input.set(Some(typed));
+                                },
+                            )
+                        }
+                    }
+                    .into_any()
+                },
+                None => element! {
+                    Text(
+                        weight: Weight::Light,
+                        content: "n: subscribe to a new channel, s: (un)subscribe, Enter: send",
+                    )
+                }
+                .into_any(),
+            })
+        }
+    }
+}
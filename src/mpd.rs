@@ -1,84 +1,569 @@
 use std::{
-    net::ToSocketAddrs,
+    future::Future,
     ops::{Deref, DerefMut},
-    sync::{Arc, RwLock, RwLockWriteGuard},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    task::Poll,
+    time::Duration,
 };
 
-use event_listener::Event;
-use mpd::Client;
+use event_listener::{Event, EventListener};
+use mailbox::Mailbox;
+use smol::lock::{Mutex, MutexGuardArc};
+
+mod address;
+mod capabilities;
+mod commands;
+mod error;
+mod mailbox;
+#[cfg(test)]
+pub mod mock;
+mod proto;
+#[cfg(test)]
+mod tests;
+mod trace;
+mod types;
+
+pub use address::{MpdAddress, DEFAULT_PORT};
+pub use capabilities::{Capabilities, Feature};
+pub use commands::CommandList;
+pub use error::{MpdError, MpdResult};
+pub use proto::{Ack, Command, Connection, Response, Timeouts, Version};
+pub use trace::{Trace, TraceReplay};
+pub use types::{Message, Output, QueuePlace, Single, Song, State, Status};
+
+/// MPD subsystems which can be changed on the server side, as reported by `idle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Subsystem {
+    Database,
+    Update,
+    StoredPlaylist,
+    Playlist,
+    Player,
+    Mixer,
+    Output,
+    Options,
+    Partition,
+    Sticker,
+    Subscription,
+    Message,
+    Neighbor,
+    Mount,
+}
+
+impl Subsystem {
+    /// Every known subsystem, in the order of their discriminants.
+    pub const ALL: [Subsystem; 14] = [
+        Subsystem::Database,
+        Subsystem::Update,
+        Subsystem::StoredPlaylist,
+        Subsystem::Playlist,
+        Subsystem::Player,
+        Subsystem::Mixer,
+        Subsystem::Output,
+        Subsystem::Options,
+        Subsystem::Partition,
+        Subsystem::Sticker,
+        Subsystem::Subscription,
+        Subsystem::Message,
+        Subsystem::Neighbor,
+        Subsystem::Mount,
+    ];
+
+    /// Name of the subsystem as used by the MPD protocol.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Database => "database",
+            Subsystem::Update => "update",
+            Subsystem::StoredPlaylist => "stored_playlist",
+            Subsystem::Playlist => "playlist",
+            Subsystem::Player => "player",
+            Subsystem::Mixer => "mixer",
+            Subsystem::Output => "output",
+            Subsystem::Options => "options",
+            Subsystem::Partition => "partition",
+            Subsystem::Sticker => "sticker",
+            Subsystem::Subscription => "subscription",
+            Subsystem::Message => "message",
+            Subsystem::Neighbor => "neighbor",
+            Subsystem::Mount => "mount",
+        }
+    }
+
+    /// Find subsystem by its MPD protocol name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|subsystem| subsystem.name() == name)
+    }
+}
+
+/// How [`MpdClient`] opens every connection to the MPD server.
+#[derive(Debug, Clone)]
+pub struct MpdOptions {
+    pub addr: MpdAddress,
+    pub password: Option<String>,
+    pub timeouts: Timeouts,
+    /// Record the protocol of every connection, see [`Trace`].
+    pub trace: Option<Arc<Trace>>,
+}
+
+/// What woke up the idle connection.
+enum IdleWake {
+    /// Server has started answering `idle`, or the connection has failed.
+    Answered(MpdResult<()>),
+    Keepalive,
+    Replaced,
+}
+
+/// Wait until it's time to check a silent connection, or forever if `keepalive` is `None`.
+async fn keepalive(keepalive: Option<Duration>) {
+    match keepalive {
+        Some(keepalive) => {
+            smol::Timer::after(keepalive).await;
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// State of the connection to the MPD server.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    #[default]
+    Connected,
+    /// Connection is lost, and the numbered attempt to restore it is in progress.
+    Reconnecting(u32),
+    /// Every reconnection attempt has failed, see [`MpdClient::reconnect`].
+    Failed,
+    /// Client is closed by [`MpdClient::close`], and is never restored.
+    Closed,
+}
+
+/// How many times [`MpdClient`] tries to restore a lost connection before giving up.
+const RECONNECT_ATTEMPTS: u32 = 10;
+/// Delay before the second reconnection attempt, doubled after each next one.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(250);
+/// Upper bound for the delay between reconnection attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How many connections [`MpdClient`] may open for bulk queries.
+const BULK_CONNECTIONS: usize = 2;
+
+type Events = Arc<[Event; Subsystem::ALL.len()]>;
+/// Pooled connection, which is opened on demand if it's empty.
+type Slot = Arc<Mutex<Option<Connection>>>;
 
 /// Connection to the MPD server and a point of MPD request's configuration.
 ///
-/// Can be shared between threads and cloned, but uses RwLock inside to manage the client.
+/// Can be shared between tasks and cloned, and manages a small pool of connections,
+/// each with its own role, so a slow request never holds up an unrelated quick one:
+///
+/// - idle connection is parked in `idle` to deliver server side changes as events,
+///   one per [`Subsystem`], see [`MpdClient::wait_for`];
+/// - command connection is for quick requests, see [`MpdClient::bind`];
+/// - bulk connections are for large queries, see [`MpdClient::bind_bulk`];
+/// - mailbox connection holds channel subscriptions, see [`MpdClient::subscribe`].
+///
+/// Connections are guarded by async Mutexes, so waiting for them never blocks the
+/// executor. Every connection is restored in the background when lost.
+///
+/// Every connection uses the same partition, see [`MpdClient::switch_partition`].
 #[derive(Debug, Clone)]
 pub struct MpdClient {
-    client: Arc<RwLock<Client>>,
-    event: Arc<Event>,
+    command: Slot,
+    bulk: Arc<[Slot; BULK_CONNECTIONS]>,
+    next_bulk: Arc<AtomicUsize>,
+    events: Events,
+    options: Arc<MpdOptions>,
+    /// Partition for every connection, or `None` for the default one.
+    partition: Arc<RwLock<Option<Arc<str>>>>,
+    state: Arc<RwLock<ConnectionState>>,
+    capabilities: Arc<RwLock<Arc<Capabilities>>>,
+    /// Incremented on every new connection, so stale idle tasks know when to quit.
+    generation: Arc<AtomicU64>,
+    /// Notified when the idle connection is replaced, to interrupt the stale one.
+    idle_replaced: Arc<Event>,
+    mailbox: Arc<Mailbox>,
 }
 
 impl MpdClient {
     /// Create a new [`MpdClient`] and connect it to `addr` with optional `password`.
-    pub fn new(addr: impl ToSocketAddrs, password: Option<impl AsRef<str>>) -> eyre::Result<Self> {
-        let mut client = Client::connect(addr)?;
-        if let Some(password) = password {
-            client.login(password.as_ref())?;
+    pub async fn new(addr: MpdAddress, password: Option<impl AsRef<str>>) -> MpdResult<Self> {
+        let password = password.map(|password| password.as_ref().to_owned());
+        let timeouts = Timeouts::default();
+        Self::with_options(MpdOptions { addr, password, timeouts, trace: None }).await
+    }
+
+    /// Create a new [`MpdClient`] and connect it as described by `options`.
+    pub async fn with_options(options: MpdOptions) -> MpdResult<Self> {
+        let (connection, idle_connection, capabilities) = Self::open_all(&options, None).await?;
+
+        let this = Self {
+            command: Arc::new(Mutex::new(Some(connection))),
+            bulk: Arc::new(std::array::from_fn(|_| Arc::new(Mutex::new(None)))),
+            next_bulk: Arc::new(AtomicUsize::new(0)),
+            events: Arc::new(std::array::from_fn(|_| Event::new())),
+            options: Arc::new(options),
+            partition: Arc::new(RwLock::new(None)),
+            state: Arc::new(RwLock::new(ConnectionState::Connected)),
+            capabilities: Arc::new(RwLock::new(Arc::new(capabilities))),
+            generation: Arc::new(AtomicU64::new(0)),
+            idle_replaced: Arc::new(Event::new()),
+            mailbox: Arc::default(),
+        };
+        this.spawn_idle(idle_connection);
+        this.spawn_keepalive();
+
+        Ok(this)
+    }
+
+    /// Current state of the connection to the MPD server.
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.state.read().unwrap()
+    }
+
+    /// Capabilities of the MPD server, updated on every reconnection.
+    pub fn capabilities(&self) -> Arc<Capabilities> {
+        self.capabilities.read().unwrap().clone()
+    }
+
+    /// Stop listening for changes and refuse every next request. Connections are closed
+    /// once every clone of the client is dropped.
+    pub fn close(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.idle_replaced.notify(usize::MAX);
+        self.mailbox.close();
+        self.set_state(ConnectionState::Closed);
+    }
+
+    /// Restore the connection in the background, unless it's already being restored
+    /// or the client is closed.
+    ///
+    /// Called automatically when the connection breaks, but can also be used to retry
+    /// after [`ConnectionState::Failed`].
+    pub fn reconnect(&self) {
+        {
+            let mut state = self.state.write().unwrap();
+            if matches!(*state, ConnectionState::Reconnecting(_) | ConnectionState::Closed) {
+                return;
+            }
+            *state = ConnectionState::Reconnecting(1);
         }
+        self.notify_all();
 
-        Ok(Self { client: Arc::new(RwLock::new(client)), event: Arc::new(Event::new()) })
+        let this = self.clone();
+        smol::spawn(async move {
+            let mut delay = RECONNECT_INITIAL_DELAY;
+            for attempt in 1..=RECONNECT_ATTEMPTS {
+                if this.connection_state() == ConnectionState::Closed {
+                    return;
+                }
+                this.set_state(ConnectionState::Reconnecting(attempt));
+                let partition = this.partition();
+                match Self::open_all(&this.options, partition.as_deref()).await {
+                    Ok(_) if this.connection_state() == ConnectionState::Closed => return,
+                    Ok((connection, idle_connection, capabilities)) => {
+                        *this.capabilities.write().unwrap() = Arc::new(capabilities);
+                        *this.command.lock().await = Some(connection);
+                        for slot in this.bulk.iter() {
+                            slot.lock().await.take();
+                        }
+                        this.spawn_idle(idle_connection);
+                        tracing::info!("Reconnected to MPD after {attempt} attempt(s)");
+                        this.set_state(ConnectionState::Connected);
+                        if let Err(error) = this.restore_mailbox().await {
+                            tracing::error!("Restoring MPD channel subscriptions: {error}");
+                        }
+                        return;
+                    },
+                    Err(error) => {
+                        tracing::warn!("Reconnecting to MPD, attempt {attempt}: {error}");
+                    },
+                }
+                smol::Timer::after(delay).await;
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            }
+            tracing::error!("Giving up reconnecting to MPD");
+            this.set_state(ConnectionState::Failed);
+        })
+        .detach();
     }
 
-    /// Wait for write access to the [`MpdClient`] and bind it to the current thread.
-    pub async fn bind(&mut self) -> MpdGuard<'_> {
-        MpdGuard { guard: self.client.write().unwrap(), event: None }
+    /// Inspect an error returned by an MPD request, and restore the connection if
+    /// the error means it is broken.
+    pub fn handle_error(&self, error: &MpdError) {
+        tracing::error!("MPD request failed: {error}");
+        if matches!(error, MpdError::Io(_) | MpdError::Timeout { .. } | MpdError::Protocol(_)) {
+            self.reconnect();
+        }
     }
 
-    /// Wait for write access to the [`MpdClient`], bind it to the current thread, and
-    /// send an update to every [`MpdClient::wait_for_update`] when the access ends.
-    pub async fn bind_then_notify(&mut self) -> MpdGuard<'_> {
-        MpdGuard { guard: self.client.write().unwrap(), event: Some(self.event.clone()) }
+    /// Partition used by every connection, or `None` for the default one.
+    pub fn partition(&self) -> Option<Arc<str>> {
+        self.partition.read().unwrap().clone()
     }
 
-    /// Notify every [`MpdClient::wait_for_update`].
-    pub async fn notify_update(&self) {
-        self.event.notify(usize::MAX);
+    /// Switch every connection to the partition `name`, and wake everyone up to
+    /// refresh, since the queue, the player and outputs are now different.
+    pub async fn switch_partition(&self, name: &str) -> MpdResult<()> {
+        // Nothing is switched until both connections are in the partition, so a failure
+        // leaves every connection in the old one.
+        let idle = Self::open(&self.options, Some(name)).await?;
+        let mut command = self.bind().await?;
+        command.partition(name).await?;
+        *self.partition.write().unwrap() = Some(Arc::from(name));
+        for slot in self.bulk.iter() {
+            slot.lock().await.take();
+        }
+        self.spawn_idle(idle);
+        drop(command);
+
+        tracing::info!("Switched to MPD partition {name}");
+        self.notify_all();
+        Ok(())
+    }
+
+    /// Open command and idle connections, and ask the server about its capabilities.
+    async fn open_all(
+        options: &MpdOptions,
+        partition: Option<&str>,
+    ) -> MpdResult<(Connection, Connection, Capabilities)> {
+        let (mut connection, idle_connection) =
+            smol::future::try_zip(Self::open(options, partition), Self::open(options, partition))
+                .await?;
+        let capabilities = Capabilities::query(&mut connection).await?;
+        tracing::info!("Connected to MPD {}", capabilities.version);
+
+        Ok((connection, idle_connection, capabilities))
+    }
+
+    /// Open a new connection, log in with the password and switch to the `partition`.
+    async fn open(options: &MpdOptions, partition: Option<&str>) -> MpdResult<Connection> {
+        let mut connection = Connection::open(options).await?;
+        if let Some(password) = &options.password {
+            connection.password(password).await?;
+        }
+        if let Some(partition) = partition {
+            connection.partition(partition).await?;
+        }
+
+        Ok(connection)
     }
 
-    /// Wait for updates sent by [`MpdClient::notify_update`] or on drop
-    /// of [`MpdGuard`] created by [`MpdClient::client_with_notify`].
-    pub async fn wait_for_update(&self) {
-        let listener = self.event.listen();
-        listener.await;
+    /// Start listening for changes on `connection`, replacing the previous listener.
+    fn spawn_idle(&self, connection: Connection) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.idle_replaced.notify(usize::MAX);
+        smol::spawn(self.clone().idle_loop(connection, generation)).detach();
+    }
+
+    /// Park `connection` in `idle` and notify events of every changed subsystem until
+    /// the connection breaks or a newer connection replaces it. The `idle` is interrupted
+    /// now and then to check that the server is alive, see [`Timeouts::keepalive`].
+    async fn idle_loop(self, mut connection: Connection, generation: u64) {
+        let is_current = || self.generation.load(Ordering::SeqCst) == generation;
+        loop {
+            let replaced = self.idle_replaced.listen();
+            if !is_current() {
+                break;
+            }
+            // Only waiting for the answer is raced, since a line read in part would be lost.
+            let wake = match connection.send_idle(&[]).await {
+                Ok(()) => {
+                    let answered = async { IdleWake::Answered(connection.readable().await) };
+                    let replaced = async {
+                        replaced.await;
+                        IdleWake::Replaced
+                    };
+                    let keepalive = async {
+                        keepalive(self.options.timeouts.keepalive).await;
+                        IdleWake::Keepalive
+                    };
+                    smol::future::or(answered, smol::future::or(replaced, keepalive)).await
+                },
+                Err(error) => IdleWake::Answered(Err(error)),
+            };
+            let result = match wake {
+                IdleWake::Answered(Ok(())) => connection.read_idle().await,
+                IdleWake::Answered(Err(error)) => Err(error),
+                IdleWake::Keepalive => connection.no_idle().await,
+                IdleWake::Replaced => break,
+            };
+            match result {
+                Ok(changed) => {
+                    for subsystem in changed {
+                        self.events[subsystem as usize].notify(usize::MAX);
+                    }
+                },
+                Err(error) => {
+                    tracing::error!("MPD idle connection is closed: {error}");
+                    if is_current() {
+                        self.reconnect();
+                    }
+                    break;
+                },
+            }
+        }
+    }
+
+    /// Ping pooled connections which were silent for too long, until every clone of the
+    /// client is dropped. Broken connections are dropped, to be reopened on demand.
+    fn spawn_keepalive(&self) {
+        let Some(keepalive) = self.options.timeouts.keepalive else {
+            return;
+        };
+        let command = Arc::downgrade(&self.command);
+        let bulk = Arc::downgrade(&self.bulk);
+        smol::spawn(async move {
+            loop {
+                smol::Timer::after(keepalive / 2).await;
+                let (Some(command), Some(bulk)) = (command.upgrade(), bulk.upgrade()) else {
+                    break;
+                };
+                for slot in std::iter::once(&command).chain(bulk.iter()) {
+                    // Busy connection is not silent.
+                    let Some(mut guard) = slot.try_lock_arc() else {
+                        continue;
+                    };
+                    let Some(connection) = guard.as_mut() else {
+                        continue;
+                    };
+                    if connection.silent_for() < keepalive {
+                        continue;
+                    }
+                    if let Err(error) = connection.ping().await {
+                        tracing::warn!("Pinging MPD: {error}");
+                        guard.take();
+                    }
+                }
+            }
+        })
+        .detach();
+    }
+
+    /// Change the connection state and wake everyone up to show it.
+    fn set_state(&self, state: ConnectionState) {
+        *self.state.write().unwrap() = state;
+        self.notify_all();
+    }
+
+    fn notify_all(&self) {
+        for event in self.events.iter() {
+            event.notify(usize::MAX);
+        }
     }
+
+    /// Wait for access to the command connection and bind it to the current task.
+    ///
+    /// Fails if the connection is lost and not restored yet.
+    pub async fn bind(&self) -> MpdResult<MpdGuard> {
+        self.bind_slot(self.command.lock_arc().await, false).await
+    }
+
+    /// Wait for access to the command connection, bind it to the current task, and
+    /// send an update to every [`MpdClient::wait_for`] when the access ends.
+    ///
+    /// Fails if the connection is lost and not restored yet.
+    pub async fn bind_then_notify(&self) -> MpdResult<MpdGuard> {
+        self.bind_slot(self.command.lock_arc().await, true).await
+    }
+
+    /// Wait for access to any bulk connection and bind it to the current task. Should be
+    /// used for queries with large responses, which would delay quick commands otherwise.
+    ///
+    /// Fails if the connection is lost and not restored yet.
+    pub async fn bind_bulk(&self) -> MpdResult<MpdGuard> {
+        let guard = match self.bulk.iter().find_map(|slot| slot.try_lock_arc()) {
+            Some(guard) => guard,
+            None => {
+                let index = self.next_bulk.fetch_add(1, Ordering::Relaxed) % BULK_CONNECTIONS;
+                self.bulk[index].lock_arc().await
+            },
+        };
+        self.bind_slot(guard, false).await
+    }
+
+    async fn bind_slot(
+        &self,
+        mut guard: MutexGuardArc<Option<Connection>>,
+        notify: bool,
+    ) -> MpdResult<MpdGuard> {
+        if self.connection_state() != ConnectionState::Connected {
+            return Err(MpdError::Disconnected);
+        }
+        if guard.is_none() {
+            let partition = self.partition();
+            *guard = Some(Self::open(&self.options, partition.as_deref()).await?);
+        }
+
+        Ok(MpdGuard { guard, events: self.events.clone(), notify })
+    }
+
+    /// Notify every [`MpdClient::wait_for`] of the `subsystem`.
+    pub fn notify_update(&self, subsystem: Subsystem) {
+        self.events[subsystem as usize].notify(usize::MAX);
+    }
+
+    /// Wait until any of the `subsystems` changes on the server, or until an update is
+    /// sent by [`MpdClient::notify_update`] or on drop of [`MpdGuard`] created by
+    /// [`MpdClient::bind_then_notify`].
+    pub async fn wait_for(&self, subsystems: &[Subsystem]) {
+        let mut listeners: Vec<EventListener> =
+            subsystems.iter().map(|subsystem| self.events[*subsystem as usize].listen()).collect();
+
+        std::future::poll_fn(|cx| {
+            for listener in &mut listeners {
+                if Pin::new(listener).poll(cx).is_ready() {
+                    return Poll::Ready(());
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Guard for [`MpdClient`], which binds the connection for the current
+/// task and notifies subscribers for UI updates on drop if enabled.
+pub struct MpdGuard {
+    guard: MutexGuardArc<Option<Connection>>,
+    events: Events,
+    notify: bool,
 }
 
-/// RwGuard for [`MpdClient`], which binds the client for the current
-/// thread and notifies subscribers for UI updates on drop if enabled.
-pub struct MpdGuard<'a> {
-    guard: RwLockWriteGuard<'a, Client>,
-    event: Option<Arc<Event>>,
+impl MpdGuard {
+    /// Send every command of the `list` in a single round-trip, and get a response for
+    /// each of them. On failure [`MpdError::Ack`] holds the index of the failed command.
+    ///
+    /// Subscribers are notified once on drop, no matter how many commands are in the list.
+    pub async fn batch(&mut self, list: &CommandList) -> MpdResult<Vec<Response>> {
+        self.notify = true;
+        self.execute_list(list.commands()).await
+    }
 }
 
-impl Deref for MpdGuard<'_> {
-    type Target = Client;
+impl Deref for MpdGuard {
+    type Target = Connection;
 
     fn deref(&self) -> &Self::Target {
-        &*self.guard
+        self.guard.as_ref().expect("Guard is created only for an open connection")
     }
 }
 
-impl DerefMut for MpdGuard<'_> {
+impl DerefMut for MpdGuard {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut *self.guard
+        self.guard.as_mut().expect("Guard is created only for an open connection")
     }
 }
 
-impl Drop for MpdGuard<'_> {
+impl Drop for MpdGuard {
     fn drop(&mut self) {
-        if let Some(event) = self.event.clone() {
-            smol::spawn(async move {
+        if self.notify {
+            for event in self.events.iter() {
                 event.notify(usize::MAX);
-            })
-            .detach();
+            }
         }
     }
 }
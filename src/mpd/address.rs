@@ -0,0 +1,149 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    fmt, io,
+    net::TcpStream,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use smol::{
+    io::{AsyncRead, AsyncWrite},
+    Async,
+};
+
+/// Default port of the MPD server.
+pub const DEFAULT_PORT: u16 = 6600;
+
+/// Address of the MPD server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MpdAddress {
+    Tcp { host: String, port: u16 },
+    /// Path to a unix domain socket.
+    Unix(PathBuf),
+    /// Name of an abstract unix domain socket, without the leading `@`.
+    Abstract(String),
+}
+
+impl MpdAddress {
+    /// Parse the `host` in the format of the `MPD_HOST` environment variable, which is
+    /// used by mpc and other clients: `[password@]host`, where host is either a hostname,
+    /// a path to a unix socket (`/run/mpd/socket`, `~/.mpd/socket`), or an abstract
+    /// socket name (`@mpd`). The `port` is used only by TCP addresses.
+    ///
+    /// Returns the address and a password, if there is any.
+    pub fn from_host(host: &str, port: u16) -> (Self, Option<String>) {
+        let (password, host) = match host.split_once('@') {
+            Some((password, host)) if !password.is_empty() => (Some(password.to_owned()), host),
+            _ => (None, host),
+        };
+
+        let address = if let Some(name) = host.strip_prefix('@') {
+            MpdAddress::Abstract(name.to_owned())
+        } else if host.starts_with('/') {
+            MpdAddress::Unix(PathBuf::from(host))
+        } else if let Some(path) = host.strip_prefix("~/") {
+            let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+            MpdAddress::Unix(home.join(path))
+        } else {
+            MpdAddress::Tcp { host: host.to_owned(), port }
+        };
+
+        (address, password)
+    }
+
+    /// Open a new stream to the MPD server.
+    pub async fn connect(&self) -> io::Result<MpdStream> {
+        match self {
+            MpdAddress::Tcp { host, port } => {
+                let mut last_error = None;
+                for addr in smol::net::resolve((host.as_str(), *port)).await? {
+                    match Async::<TcpStream>::connect(addr).await {
+                        Ok(stream) => return Ok(MpdStream::Tcp(stream)),
+                        Err(error) => last_error = Some(error),
+                    }
+                }
+                Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{self} is not resolved"))
+                }))
+            },
+            #[cfg(unix)]
+            MpdAddress::Unix(path) => Async::<UnixStream>::connect(path).await.map(MpdStream::Unix),
+            #[cfg(target_os = "linux")]
+            MpdAddress::Abstract(name) => {
+                use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+                let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+                Async::new(UnixStream::connect_addr(&addr)?).map(MpdStream::Unix)
+            },
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{self} is not supported on this platform"),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MpdAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MpdAddress::Tcp { host, port } => write!(f, "{host}:{port}"),
+            MpdAddress::Unix(path) => write!(f, "{}", path.display()),
+            MpdAddress::Abstract(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+/// Async stream to the MPD server, either over TCP or a unix domain socket.
+#[derive(Debug)]
+pub enum MpdStream {
+    Tcp(Async<TcpStream>),
+    #[cfg(unix)]
+    Unix(Async<UnixStream>),
+}
+
+impl AsyncRead for MpdStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MpdStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            MpdStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MpdStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MpdStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            MpdStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MpdStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            MpdStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MpdStream::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(unix)]
+            MpdStream::Unix(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
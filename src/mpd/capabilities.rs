@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+
+use super::{Connection, MpdResult, Version};
+
+/// Optional MPD features, which depend on the server version or configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// `oneshot` state of the single mode.
+    SingleOneshot,
+    /// Multiple partitions with their own queues and outputs.
+    Partitions,
+}
+
+impl Feature {
+    /// First protocol version which has the feature.
+    fn since(&self) -> Version {
+        match self {
+            Feature::SingleOneshot => Version::new(0, 21, 0),
+            Feature::Partitions => Version::new(0, 22, 0),
+        }
+    }
+
+    /// Command which has to be available for the feature, if there is one.
+    fn command(&self) -> Option<&'static str> {
+        match self {
+            Feature::SingleOneshot => None,
+            Feature::Partitions => Some("partition"),
+        }
+    }
+}
+
+/// What the connected MPD server can do, derived from its protocol version, available
+/// `commands` and enabled `tagtypes`.
+///
+/// Commands depend on permissions of the current user, so capabilities are queried
+/// only after the `password` is sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: Version,
+    commands: HashSet<String>,
+    not_commands: HashSet<String>,
+    tag_types: HashSet<String>,
+}
+
+impl Capabilities {
+    /// Ask the server about its capabilities using the `connection`.
+    pub async fn query(connection: &mut Connection) -> MpdResult<Self> {
+        Ok(Self {
+            version: connection.version(),
+            commands: connection.commands().await?.into_iter().collect(),
+            not_commands: connection.not_commands().await?.into_iter().collect(),
+            tag_types: connection
+                .tag_types()
+                .await?
+                .into_iter()
+                .map(|tag| tag.to_ascii_lowercase())
+                .collect(),
+        })
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.version >= feature.since()
+            && feature.command().is_none_or(|command| self.has_command(command))
+    }
+
+    /// Whether the `command` is known by the server and allowed for the current user.
+    pub fn has_command(&self, command: &str) -> bool {
+        self.commands.contains(command)
+    }
+
+    /// Why the `command` can't be used, or `None` if it can.
+    pub fn unavailable_reason(&self, command: &str) -> Option<&'static str> {
+        if self.has_command(command) {
+            None
+        } else if self.not_commands.contains(command) {
+            Some("not permitted for the current user")
+        } else {
+            Some("not supported by the server")
+        }
+    }
+
+    /// Whether the `tag` is enabled on the server, case insensitive.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tag_types.contains(&tag.to_ascii_lowercase())
+    }
+}
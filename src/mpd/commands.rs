@@ -0,0 +1,331 @@
+//! Typed wrappers for MPD commands.
+
+use std::ops::Range;
+
+use super::{
+    Command, Connection, Message, MpdError, MpdResult, Output, Response, Single, Song, Status,
+    Subsystem,
+};
+
+impl Connection {
+    pub async fn password(&mut self, password: &str) -> MpdResult<()> {
+        self.execute(&Command::new("password").arg(password)).await.map(drop)
+    }
+
+    /// Do nothing, just keep the connection alive.
+    pub async fn ping(&mut self) -> MpdResult<()> {
+        self.execute(&Command::new("ping")).await.map(drop)
+    }
+
+    pub async fn status(&mut self) -> MpdResult<Status> {
+        Status::from_pairs(&self.execute(&Command::new("status")).await?.pairs)
+    }
+
+    pub async fn current_song(&mut self) -> MpdResult<Option<Song>> {
+        let response = self.execute(&Command::new("currentsong")).await?;
+        if response.pairs.is_empty() {
+            return Ok(None);
+        }
+
+        Song::from_pairs(&response.pairs).map(Some)
+    }
+
+    /// Every song in the queue.
+    pub async fn playlist_info(&mut self) -> MpdResult<Vec<Song>> {
+        Song::list_from_pairs(&self.execute(&Command::new("playlistinfo")).await?.pairs)
+    }
+
+    /// Songs of the queue at positions in every one of the `ranges`, in a single round-trip.
+    pub async fn playlist_info_ranges(&mut self, ranges: &[Range<u32>]) -> MpdResult<Vec<Song>> {
+        if ranges.is_empty() {
+            return Ok(Vec::new());
+        }
+        let commands: Vec<Command> = ranges
+            .iter()
+            .map(|range| Command::new("playlistinfo").arg(format!("{}:{}", range.start, range.end)))
+            .collect();
+        let mut songs = Vec::new();
+        for response in self.execute_list(&commands).await? {
+            songs.extend(Song::list_from_pairs(&response.pairs)?);
+        }
+        Ok(songs)
+    }
+
+    /// Songs of the queue with every one of the `ids`, in a single round-trip.
+    pub async fn playlist_ids(&mut self, ids: &[u32]) -> MpdResult<Vec<Song>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let commands: Vec<Command> =
+            ids.iter().map(|&id| Command::new("playlistid").arg(id)).collect();
+        let mut songs = Vec::new();
+        for response in self.execute_list(&commands).await? {
+            songs.extend(Song::list_from_pairs(&response.pairs)?);
+        }
+        Ok(songs)
+    }
+
+    /// Status together with positions and ids of queue songs changed since the queue
+    /// `version`, so they match each other.
+    pub async fn status_and_queue_changes(
+        &mut self,
+        version: u32,
+    ) -> MpdResult<(Status, Vec<(u32, u32)>)> {
+        let commands = [Command::new("status"), Command::new("plchangesposid").arg(version)];
+        let [status, changes] = exactly(self.execute_list(&commands).await?)?;
+
+        let mut changed = Vec::new();
+        let mut pos = None;
+        for (key, value) in &changes.pairs {
+            match key.as_str() {
+                "cpos" => pos = Some(value.parse()?),
+                "Id" => match pos.take() {
+                    Some(pos) => changed.push((pos, value.parse()?)),
+                    None => return Err(MpdError::Parse(format!("Song id {value} without cpos"))),
+                },
+                _ => {},
+            }
+        }
+        Ok((Status::from_pairs(&status.pairs)?, changed))
+    }
+
+    pub async fn play(&mut self) -> MpdResult<()> {
+        self.execute(&Command::new("play")).await.map(drop)
+    }
+
+    /// Play the song with `id` from the queue.
+    pub async fn play_id(&mut self, id: u32) -> MpdResult<()> {
+        self.execute(&Command::new("playid").arg(id)).await.map(drop)
+    }
+
+    pub async fn toggle_pause(&mut self) -> MpdResult<()> {
+        self.execute(&Command::new("pause")).await.map(drop)
+    }
+
+    pub async fn stop(&mut self) -> MpdResult<()> {
+        self.execute(&Command::new("stop")).await.map(drop)
+    }
+
+    pub async fn next(&mut self) -> MpdResult<()> {
+        self.execute(&Command::new("next")).await.map(drop)
+    }
+
+    pub async fn prev(&mut self) -> MpdResult<()> {
+        self.execute(&Command::new("previous")).await.map(drop)
+    }
+
+    /// Seek to the `position` in seconds within the current song.
+    pub async fn seek(&mut self, position: f64) -> MpdResult<()> {
+        self.execute(&Command::new("seekcur").arg(position.max(0.0))).await.map(drop)
+    }
+
+    pub async fn set_volume(&mut self, volume: u8) -> MpdResult<()> {
+        self.execute(&Command::new("setvol").arg(volume.min(100))).await.map(drop)
+    }
+
+    pub async fn repeat(&mut self, enabled: bool) -> MpdResult<()> {
+        self.execute(&Command::new("repeat").arg(u8::from(enabled))).await.map(drop)
+    }
+
+    pub async fn random(&mut self, enabled: bool) -> MpdResult<()> {
+        self.execute(&Command::new("random").arg(u8::from(enabled))).await.map(drop)
+    }
+
+    pub async fn single(&mut self, single: Single) -> MpdResult<()> {
+        self.execute(&Command::new("single").arg(single.name())).await.map(drop)
+    }
+
+    pub async fn consume(&mut self, enabled: bool) -> MpdResult<()> {
+        self.execute(&Command::new("consume").arg(u8::from(enabled))).await.map(drop)
+    }
+
+    /// Switch the connection to the partition `name`.
+    pub async fn partition(&mut self, name: &str) -> MpdResult<()> {
+        self.execute(&Command::new("partition").arg(name)).await.map(drop)
+    }
+
+    pub async fn list_partitions(&mut self) -> MpdResult<Vec<String>> {
+        let response = self.execute(&Command::new("listpartitions")).await?;
+        Ok(response.pairs.into_iter().map(|(_, name)| name).collect())
+    }
+
+    pub async fn new_partition(&mut self, name: &str) -> MpdResult<()> {
+        self.execute(&Command::new("newpartition").arg(name)).await.map(drop)
+    }
+
+    /// Delete the partition `name`, which must have no clients and no outputs.
+    pub async fn delete_partition(&mut self, name: &str) -> MpdResult<()> {
+        self.execute(&Command::new("delpartition").arg(name)).await.map(drop)
+    }
+
+    pub async fn outputs(&mut self) -> MpdResult<Vec<Output>> {
+        Output::list_from_pairs(&self.execute(&Command::new("outputs")).await?.pairs)
+    }
+
+    /// Move the output `name` to the partition of the connection.
+    pub async fn move_output(&mut self, name: &str) -> MpdResult<()> {
+        self.execute(&Command::new("moveoutput").arg(name)).await.map(drop)
+    }
+
+    pub async fn subscribe(&mut self, channel: &str) -> MpdResult<()> {
+        self.execute(&Command::new("subscribe").arg(channel)).await.map(drop)
+    }
+
+    pub async fn unsubscribe(&mut self, channel: &str) -> MpdResult<()> {
+        self.execute(&Command::new("unsubscribe").arg(channel)).await.map(drop)
+    }
+
+    /// Channels which have at least one subscriber.
+    pub async fn channels(&mut self) -> MpdResult<Vec<String>> {
+        let response = self.execute(&Command::new("channels")).await?;
+        Ok(response.pairs.into_iter().map(|(_, channel)| channel).collect())
+    }
+
+    /// Take messages received on subscribed channels of the connection.
+    pub async fn read_messages(&mut self) -> MpdResult<Vec<Message>> {
+        Ok(Message::list_from_pairs(&self.execute(&Command::new("readmessages")).await?.pairs))
+    }
+
+    pub async fn send_message(&mut self, channel: &str, text: &str) -> MpdResult<()> {
+        self.execute(&Command::new("sendmessage").arg(channel).arg(text)).await.map(drop)
+    }
+
+    /// Commands allowed for the current user.
+    pub async fn commands(&mut self) -> MpdResult<Vec<String>> {
+        let response = self.execute(&Command::new("commands")).await?;
+        Ok(response.pairs.into_iter().map(|(_, command)| command).collect())
+    }
+
+    /// Commands known by the server, but not allowed for the current user.
+    pub async fn not_commands(&mut self) -> MpdResult<Vec<String>> {
+        let response = self.execute(&Command::new("notcommands")).await?;
+        Ok(response.pairs.into_iter().map(|(_, command)| command).collect())
+    }
+
+    /// Tags enabled on the server.
+    pub async fn tag_types(&mut self) -> MpdResult<Vec<String>> {
+        let response = self.execute(&Command::new("tagtypes")).await?;
+        Ok(response.pairs.into_iter().map(|(_, tag)| tag).collect())
+    }
+
+    /// Start waiting until something changes on the server, for every subsystem if
+    /// `subsystems` is empty. Changed subsystems are read by [`Connection::read_idle`] once
+    /// the connection is [`Connection::readable`], or by [`Connection::no_idle`].
+    pub async fn send_idle(&mut self, subsystems: &[Subsystem]) -> MpdResult<()> {
+        let command = subsystems
+            .iter()
+            .fold(Command::new("idle"), |command, subsystem| command.arg(subsystem.name()));
+        self.send(&command).await
+    }
+
+    /// Read subsystems changed since `idle` was sent.
+    pub async fn read_idle(&mut self) -> MpdResult<Vec<Subsystem>> {
+        Ok(changed_subsystems(&self.read("idle").await?.pairs))
+    }
+
+    /// Interrupt `idle`, which was sent before, and return subsystems changed so far.
+    /// MPD ignores `noidle` if it has already answered `idle`, so the answer is read either
+    /// way.
+    pub async fn no_idle(&mut self) -> MpdResult<Vec<Subsystem>> {
+        self.send(&Command::new("noidle")).await?;
+        self.read_idle().await
+    }
+}
+
+/// Responses of a command list with `N` commands.
+fn exactly<const N: usize>(responses: Vec<Response>) -> MpdResult<[Response; N]> {
+    responses.try_into().map_err(|responses: Vec<Response>| {
+        MpdError::Parse(format!("Expected {N} responses, got {}", responses.len()))
+    })
+}
+
+fn changed_subsystems(pairs: &[(String, String)]) -> Vec<Subsystem> {
+    pairs
+        .iter()
+        .filter(|(key, _)| key == "changed")
+        .filter_map(|(_, name)| {
+            let subsystem = Subsystem::from_name(name);
+            if subsystem.is_none() {
+                tracing::warn!("Unknown MPD subsystem: {name}");
+            }
+            subsystem
+        })
+        .collect()
+}
+
+/// Batch of typed commands, sent at once as a command list by [`super::MpdGuard::batch`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandList {
+    commands: Vec<Command>,
+}
+
+impl CommandList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an arbitrary command to the list.
+    pub fn push(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Add a song by `uri` at the position `pos` in the queue.
+    pub fn add_id(self, uri: &str, pos: u32) -> Self {
+        self.push(Command::new("addid").arg(uri).arg(pos))
+    }
+
+    pub fn delete_id(self, id: u32) -> Self {
+        self.push(Command::new("deleteid").arg(id))
+    }
+
+    /// Delete songs at positions in the `range` from the queue.
+    pub fn delete_range(self, range: Range<u32>) -> Self {
+        self.push(Command::new("delete").arg(format!("{}:{}", range.start, range.end)))
+    }
+
+    /// Move the song with `id` to the position `to` in the queue.
+    pub fn move_id(self, id: u32, to: u32) -> Self {
+        self.push(Command::new("moveid").arg(id).arg(to))
+    }
+
+    /// Move songs at positions in the `range` to start at the position `to`.
+    pub fn move_range(self, range: Range<u32>, to: u32) -> Self {
+        self.push(Command::new("move").arg(format!("{}:{}", range.start, range.end)).arg(to))
+    }
+
+    pub fn play_id(self, id: u32) -> Self {
+        self.push(Command::new("playid").arg(id))
+    }
+
+    /// Set the priority of the song with `id`, songs with higher ones play first in random.
+    pub fn prio_id(self, prio: u8, id: u32) -> Self {
+        self.push(Command::new("prioid").arg(prio).arg(id))
+    }
+
+    /// Set the priority of songs at positions in the `range`.
+    pub fn prio_range(self, prio: u8, range: Range<u32>) -> Self {
+        self.push(Command::new("prio").arg(prio).arg(format!("{}:{}", range.start, range.end)))
+    }
+
+    /// Append a song by `uri` to the stored playlist `name`, creating it if needed.
+    pub fn playlist_add(self, name: &str, uri: &str) -> Self {
+        self.push(Command::new("playlistadd").arg(name).arg(uri))
+    }
+
+    pub fn clear(self) -> Self {
+        self.push(Command::new("clear"))
+    }
+
+    pub fn shuffle(self) -> Self {
+        self.push(Command::new("shuffle"))
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fmt, io,
+    num::{ParseFloatError, ParseIntError},
+    time::{Duration, TryFromFloatSecsError},
+};
+
+use super::Ack;
+
+/// Result of an MPD request.
+pub type MpdResult<T> = Result<T, MpdError>;
+
+/// Error of an MPD request.
+#[derive(Debug)]
+pub enum MpdError {
+    /// Server rejected the command, connection is still usable.
+    Ack(Ack),
+    /// Connection is broken, and has to be restored.
+    Io(io::Error),
+    /// Server hasn't answered the command in time, so the connection has to be restored.
+    Timeout { command: String, after: Duration },
+    /// Server response is not understood.
+    Parse(String),
+    /// Server response is broken before its end, so the rest of it can't be skipped and
+    /// the connection has to be restored.
+    Protocol(String),
+    /// Connection is being restored, or restoring it has failed.
+    Disconnected,
+}
+
+impl MpdError {
+    /// Whether the connection is still usable after this error.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, MpdError::Ack(_) | MpdError::Parse(_))
+    }
+}
+
+impl fmt::Display for MpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MpdError::Ack(ack) => write!(f, "{ack}"),
+            MpdError::Io(error) => write!(f, "MPD connection error: {error}"),
+            MpdError::Timeout { command, after } => {
+                write!(f, "MPD hasn't answered {command} in {after:?}")
+            },
+            MpdError::Parse(message) => write!(f, "Unexpected MPD response: {message}"),
+            MpdError::Protocol(message) => write!(f, "Broken MPD response: {message}"),
+            MpdError::Disconnected => write!(f, "Not connected to MPD"),
+        }
+    }
+}
+
+impl std::error::Error for MpdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MpdError::Ack(ack) => Some(ack),
+            MpdError::Io(error) => Some(error),
+            MpdError::Timeout { .. }
+            | MpdError::Parse(_)
+            | MpdError::Protocol(_)
+            | MpdError::Disconnected => None,
+        }
+    }
+}
+
+impl From<Ack> for MpdError {
+    fn from(ack: Ack) -> Self {
+        MpdError::Ack(ack)
+    }
+}
+
+impl From<io::Error> for MpdError {
+    fn from(error: io::Error) -> Self {
+        MpdError::Io(error)
+    }
+}
+
+impl From<ParseIntError> for MpdError {
+    fn from(error: ParseIntError) -> Self {
+        MpdError::Parse(error.to_string())
+    }
+}
+
+impl From<ParseFloatError> for MpdError {
+    fn from(error: ParseFloatError) -> Self {
+        MpdError::Parse(error.to_string())
+    }
+}
+
+impl From<TryFromFloatSecsError> for MpdError {
+    fn from(error: TryFromFloatSecsError) -> Self {
+        MpdError::Parse(error.to_string())
+    }
+}
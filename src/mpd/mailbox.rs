@@ -0,0 +1,195 @@
+//! Client-to-client messages, received on a dedicated connection.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::{Mutex, RwLock},
+};
+
+use smol::channel::{Receiver, Sender};
+
+use super::{
+    keepalive, Connection, ConnectionState, Message, MpdClient, MpdError, MpdResult, Subsystem,
+};
+
+/// How many received messages are kept, older ones are dropped first.
+const MAX_MESSAGES: usize = 200;
+
+/// Change of subscriptions, and where to send its result.
+type Request = (Subscription, Sender<MpdResult<()>>);
+
+#[derive(Debug)]
+enum Subscription {
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+/// Subscriptions and received messages of [`MpdClient`].
+///
+/// MPD delivers messages only to the connection which has subscribed, so the mailbox has
+/// its own connection, parked in `idle` for the `message` subsystem. Subscription changes
+/// are sent to it as requests, which interrupt the `idle`.
+#[derive(Debug, Default)]
+pub(super) struct Mailbox {
+    /// Requests for the mailbox connection, or `None` if it isn't open yet.
+    requests: Mutex<Option<Sender<Request>>>,
+    /// Held while the mailbox connection is opened, so there's only one of them.
+    opening: smol::lock::Mutex<()>,
+    subscriptions: RwLock<BTreeSet<String>>,
+    messages: Mutex<VecDeque<Message>>,
+}
+
+impl Mailbox {
+    /// Stop the mailbox connection, keeping subscriptions to restore them later.
+    pub(super) fn close(&self) {
+        if let Some(requests) = self.requests.lock().unwrap().take() {
+            requests.close();
+        }
+    }
+
+    /// Requests for the mailbox connection, if it's open.
+    fn requests(&self) -> Option<Sender<Request>> {
+        let requests = self.requests.lock().unwrap();
+        requests.as_ref().filter(|requests| !requests.is_closed()).cloned()
+    }
+}
+
+/// What woke up the mailbox connection.
+enum Wake {
+    Message,
+    Request(Request),
+    Keepalive,
+    Closed,
+}
+
+impl MpdClient {
+    /// Channels subscribed by this client.
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.mailbox.subscriptions.read().unwrap().iter().cloned().collect()
+    }
+
+    /// Messages received on subscribed channels, oldest first.
+    pub fn messages(&self) -> Vec<Message> {
+        self.mailbox.messages.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Subscribe to the `channel`, and keep receiving its messages. Every update is sent
+    /// as [`Subsystem::Message`] for [`MpdClient::wait_for`].
+    pub async fn subscribe(&self, channel: &str) -> MpdResult<()> {
+        self.request_mailbox(Subscription::Subscribe(channel.to_owned())).await
+    }
+
+    pub async fn unsubscribe(&self, channel: &str) -> MpdResult<()> {
+        self.request_mailbox(Subscription::Unsubscribe(channel.to_owned())).await
+    }
+
+    async fn request_mailbox(&self, subscription: Subscription) -> MpdResult<()> {
+        let (reply, result) = smol::channel::bounded(1);
+        self.mailbox_requests()
+            .await?
+            .send((subscription, reply))
+            .await
+            .map_err(|_| MpdError::Disconnected)?;
+        result.recv().await.map_err(|_| MpdError::Disconnected)?
+    }
+
+    /// Reopen the mailbox connection with every subscription, if there are any.
+    pub(super) async fn restore_mailbox(&self) -> MpdResult<()> {
+        if self.mailbox.subscriptions.read().unwrap().is_empty() {
+            return Ok(());
+        }
+        self.mailbox.close();
+        self.mailbox_requests().await.map(drop)
+    }
+
+    /// Get access to the mailbox connection, opening it with every subscription restored
+    /// if it isn't open yet.
+    async fn mailbox_requests(&self) -> MpdResult<Sender<Request>> {
+        if let Some(requests) = self.mailbox.requests() {
+            return Ok(requests);
+        }
+        // Concurrent callers wait for the connection opened by the first one.
+        let _opening = self.mailbox.opening.lock().await;
+        if let Some(requests) = self.mailbox.requests() {
+            return Ok(requests);
+        }
+        if self.connection_state() != ConnectionState::Connected {
+            return Err(MpdError::Disconnected);
+        }
+
+        let mut connection = Self::open(&self.options, None).await?;
+        for channel in self.subscriptions() {
+            connection.subscribe(&channel).await?;
+        }
+        let (requests, receiver) = smol::channel::unbounded();
+        *self.mailbox.requests.lock().unwrap() = Some(requests.clone());
+        smol::spawn(self.clone().mailbox_loop(connection, receiver)).detach();
+
+        Ok(requests)
+    }
+
+    /// Collect messages and apply subscription requests until the mailbox is closed or
+    /// the connection breaks.
+    async fn mailbox_loop(self, mut connection: Connection, requests: Receiver<Request>) {
+        let result: MpdResult<()> = async {
+            loop {
+                let messages = connection.read_messages().await?;
+                if !messages.is_empty() {
+                    let mut received = self.mailbox.messages.lock().unwrap();
+                    received.extend(messages);
+                    let overflow = received.len().saturating_sub(MAX_MESSAGES);
+                    received.drain(..overflow);
+                    drop(received);
+                    self.notify_update(Subsystem::Message);
+                }
+
+                // Only waiting for the answer is raced, since a line read in part would be lost.
+                connection.send_idle(&[Subsystem::Message]).await?;
+                let idle = async { connection.readable().await.map(|()| Wake::Message) };
+                let request =
+                    async { Ok(requests.recv().await.map_or(Wake::Closed, Wake::Request)) };
+                let timer = async {
+                    keepalive(self.options.timeouts.keepalive).await;
+                    Ok(Wake::Keepalive)
+                };
+                let wake = smol::future::or(idle, smol::future::or(request, timer)).await?;
+                let (subscription, reply) = match wake {
+                    Wake::Message => {
+                        connection.read_idle().await?;
+                        continue;
+                    },
+                    Wake::Request(request) => request,
+                    Wake::Keepalive => {
+                        connection.no_idle().await?;
+                        continue;
+                    },
+                    Wake::Closed => return Ok(()),
+                };
+                connection.no_idle().await?;
+
+                let result = match &subscription {
+                    Subscription::Subscribe(channel) => connection.subscribe(channel).await,
+                    Subscription::Unsubscribe(channel) => connection.unsubscribe(channel).await,
+                };
+                if result.is_ok() {
+                    let mut subscriptions = self.mailbox.subscriptions.write().unwrap();
+                    match subscription {
+                        Subscription::Subscribe(channel) => subscriptions.insert(channel),
+                        Subscription::Unsubscribe(channel) => subscriptions.remove(&channel),
+                    };
+                }
+                let is_broken = result.as_ref().is_err_and(|error| !error.is_recoverable());
+                // Requester handles the error itself, so the broken connection is just left.
+                let _ = reply.try_send(result);
+                if is_broken {
+                    return Ok(());
+                }
+                self.notify_update(Subsystem::Subscription);
+            }
+        }
+        .await;
+
+        if let Err(error) = result {
+            self.handle_error(&error);
+        }
+    }
+}
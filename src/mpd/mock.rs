@@ -0,0 +1,652 @@
+//! In-process fake MPD server for tests, which speaks enough of the protocol for
+//! [`MpdClient`](super::MpdClient) and records every command it receives.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, TcpListener},
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixListener, path::Path};
+
+use event_listener::Event;
+use smol::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    Async, Task,
+};
+
+use super::{address::MpdStream, MpdAddress, Subsystem, Version};
+
+/// Canned reply of [`MockServer`] to a command.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Pairs(Vec<(String, String)>),
+    /// Pairs followed by a binary chunk.
+    Binary(Vec<(String, String)>, Vec<u8>),
+    Ack { code: u32, message: String },
+    /// Lines sent as they are before `OK`, like a broken response.
+    Raw(String),
+    /// Never answer, like a hung server.
+    Hang,
+}
+
+/// State used by [`MockServer`] to answer commands, can be changed while it's running.
+#[derive(Debug, Clone)]
+pub struct MockState {
+    pub version: Version,
+    /// Password expected by `password`, any other is rejected.
+    pub password: Option<String>,
+    pub status: Vec<(String, String)>,
+    /// Position of the current song in the `queue`.
+    pub current_song: Option<usize>,
+    /// Every song in the queue as pairs, like in `playlistinfo`. Should be changed with
+    /// methods like [`MockState::add_song`], which keep track of queue versions.
+    pub queue: Vec<Vec<(String, String)>>,
+    /// Version of the queue, increased by every change of it.
+    pub queue_version: u32,
+    /// Version of the queue in which every song of it has changed last.
+    pub song_versions: Vec<u32>,
+    pub commands: Vec<String>,
+    /// Commands which are rejected with a permission error.
+    pub not_commands: Vec<String>,
+    pub tag_types: Vec<String>,
+    /// Replies to every other command, by the command name.
+    pub replies: HashMap<String, Reply>,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            version: Version::new(0, 23, 5),
+            password: None,
+            status: pairs(&[
+                ("volume", "50"),
+                ("repeat", "0"),
+                ("random", "0"),
+                ("single", "0"),
+                ("consume", "0"),
+                ("state", "stop"),
+            ]),
+            current_song: None,
+            queue: Vec::new(),
+            queue_version: 1,
+            song_versions: Vec::new(),
+            commands: [
+                "commands", "currentsong", "getvol", "idle", "noidle", "notcommands", "partition",
+                "password", "ping", "playlistinfo", "plchanges", "plchangesposid", "readpicture",
+                "status", "tagtypes",
+            ]
+            .map(str::to_owned)
+            .to_vec(),
+            not_commands: Vec::new(),
+            tag_types: ["Artist", "Album", "Title"].map(str::to_owned).to_vec(),
+            replies: HashMap::new(),
+        }
+    }
+}
+
+impl MockState {
+    /// Append a song to the queue, with its position and a new id.
+    pub fn add_song(&mut self, file: &str, tags: &[(&str, &str)]) {
+        self.insert_song(self.queue.len(), file, tags);
+    }
+
+    /// Insert a song at `pos` in the queue, with a new id, and return the id.
+    pub fn insert_song(&mut self, pos: usize, file: &str, tags: &[(&str, &str)]) -> u32 {
+        let id = self.queue.iter().filter_map(|song| song_value(song, "Id")).max().unwrap_or(0) + 1;
+        let mut song = pairs(&[("file", file)]);
+        song.extend(pairs(tags));
+        song.extend(pairs(&[("Pos", &pos.to_string()), ("Id", &id.to_string())]));
+        self.queue.insert(pos, song);
+        self.song_versions.insert(pos, 0);
+        self.commit(Some(pos));
+        id
+    }
+
+    pub fn delete_song(&mut self, pos: usize) {
+        self.delete_songs(pos..pos + 1);
+    }
+
+    pub fn delete_songs(&mut self, range: Range<usize>) {
+        self.queue.drain(range.clone());
+        self.song_versions.drain(range);
+        self.commit(None);
+    }
+
+    pub fn move_song(&mut self, from: usize, to: usize) {
+        self.move_songs(from..from + 1, to);
+    }
+
+    /// Move songs at positions in the `range` to start at the position `to`.
+    pub fn move_songs(&mut self, range: Range<usize>, to: usize) {
+        let songs: Vec<_> = self.queue.drain(range.clone()).collect();
+        self.queue.splice(to..to, songs);
+        let versions: Vec<_> = self.song_versions.drain(range).collect();
+        self.song_versions.splice(to..to, versions);
+        self.commit(None);
+    }
+
+    /// Reverse the queue, which is a shuffle easy to check.
+    pub fn shuffle(&mut self) {
+        self.queue.reverse();
+        self.song_versions.reverse();
+        self.commit(None);
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.song_versions.clear();
+        self.commit(None);
+    }
+
+    /// Position of the song with `id` in the queue.
+    pub fn position(&self, id: u32) -> Option<usize> {
+        self.queue.iter().position(|song| song_value(song, "Id") == Some(id))
+    }
+
+    pub fn set_priority(&mut self, pos: usize, prio: u8) {
+        let song = &mut self.queue[pos];
+        song.retain(|(key, _)| key != "Prio");
+        song.push(("Prio".to_owned(), prio.to_string()));
+        self.commit(Some(pos));
+    }
+
+    /// Finish a change of the queue: increase its version, and fix positions of songs,
+    /// marking them and the `changed` one as changed in the new version.
+    fn commit(&mut self, changed: Option<usize>) {
+        self.queue_version += 1;
+        for (index, song) in self.queue.iter_mut().enumerate() {
+            let pos = song.iter_mut().find(|(key, _)| key == "Pos").expect("Songs have positions");
+            if pos.1 != index.to_string() || changed == Some(index) {
+                pos.1 = index.to_string();
+                self.song_versions[index] = self.queue_version;
+            }
+        }
+    }
+
+    /// Songs of the queue changed since its `version`, or all of them if it's unknown.
+    fn queue_changes(&self, version: u32) -> impl Iterator<Item = &Vec<(String, String)>> {
+        let is_unknown = version > self.queue_version;
+        self.queue
+            .iter()
+            .zip(&self.song_versions)
+            .filter(move |(_, &changed)| is_unknown || changed > version)
+            .map(|(song, _)| song)
+    }
+
+    /// Reply to the command `name` with `reply`, and allow it.
+    pub fn reply(&mut self, name: &str, reply: Reply) {
+        self.commands.push(name.to_owned());
+        self.replies.insert(name.to_owned(), reply);
+    }
+}
+
+/// Make owned pairs out of borrowed ones.
+pub fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+/// Fake MPD server, listening on a local socket until dropped.
+pub struct MockServer {
+    address: MpdAddress,
+    shared: Arc<Shared>,
+    _task: Task<()>,
+}
+
+struct Shared {
+    state: Mutex<MockState>,
+    received: Mutex<Vec<String>>,
+    /// Every change ever made, each client tracks how many of them it has seen.
+    changes: Mutex<Vec<Subsystem>>,
+    changed: Event,
+    /// How many times every client was dropped, so clients busy at the time notice it too.
+    disconnects: AtomicUsize,
+    disconnect: Event,
+}
+
+impl MockServer {
+    /// Start listening on a random local TCP port.
+    pub fn start(state: MockState) -> io::Result<Self> {
+        let listener = Async::<TcpListener>::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.get_ref().local_addr()?.port();
+        let address = MpdAddress::Tcp { host: Ipv4Addr::LOCALHOST.to_string(), port };
+        Ok(Self::serve(address, Listener::Tcp(listener), state))
+    }
+
+    /// Start listening on a unix socket at `path`.
+    #[cfg(unix)]
+    pub fn start_unix(path: &Path, state: MockState) -> io::Result<Self> {
+        let listener = Async::<UnixListener>::bind(path)?;
+        Ok(Self::serve(MpdAddress::Unix(path.to_owned()), Listener::Unix(listener), state))
+    }
+
+    fn serve(address: MpdAddress, listener: Listener, state: MockState) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(state),
+            received: Mutex::default(),
+            changes: Mutex::default(),
+            changed: Event::new(),
+            disconnects: AtomicUsize::new(0),
+            disconnect: Event::new(),
+        });
+        let task = smol::spawn({
+            let shared = shared.clone();
+            async move {
+                // Client tasks are cancelled with the server, when this one is dropped.
+                let mut clients = Vec::new();
+                while let Ok(stream) = listener.accept().await {
+                    let disconnects = shared.disconnects.load(Ordering::SeqCst);
+                    let stream = BufReader::new(stream);
+                    let client = Client { stream, shared: shared.clone(), disconnects };
+                    clients.push(smol::spawn(client.serve()));
+                }
+            }
+        });
+
+        Self { address, shared, _task: task }
+    }
+
+    pub fn address(&self) -> MpdAddress {
+        self.address.clone()
+    }
+
+    /// Every command line received so far, from every client.
+    pub fn received(&self) -> Vec<String> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Change the state, which is used for every next command.
+    pub fn update(&self, update: impl FnOnce(&mut MockState)) {
+        update(&mut self.shared.state.lock().unwrap());
+    }
+
+    /// Report a change of the `subsystem` to every client in `idle`.
+    pub fn notify(&self, subsystem: Subsystem) {
+        self.shared.changes.lock().unwrap().push(subsystem);
+        self.shared.changed.notify(usize::MAX);
+    }
+
+    /// Drop every client connection which is already served, as if the server was restarted.
+    pub fn disconnect_all(&self) {
+        self.shared.disconnects.fetch_add(1, Ordering::SeqCst);
+        self.shared.disconnect.notify(usize::MAX);
+    }
+}
+
+enum Listener {
+    Tcp(Async<TcpListener>),
+    #[cfg(unix)]
+    Unix(Async<UnixListener>),
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<MpdStream> {
+        match self {
+            Listener::Tcp(listener) => Ok(MpdStream::Tcp(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(MpdStream::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Connection of a single client to [`MockServer`].
+struct Client {
+    stream: BufReader<MpdStream>,
+    shared: Arc<Shared>,
+    /// Value of [`Shared::disconnects`] when the client connected.
+    disconnects: usize,
+}
+
+impl Client {
+    async fn serve(mut self) {
+        let greeting = format!("OK MPD {}\n", self.shared.state.lock().unwrap().version);
+        let mut seen_changes = self.shared.changes.lock().unwrap().len();
+        if self.write(greeting.as_bytes()).await.is_err() {
+            return;
+        }
+
+        while let Ok(Some(line)) = self.next_line().await {
+            let response = match line.as_str() {
+                "command_list_begin" | "command_list_ok_begin" => {
+                    let mut list = Vec::new();
+                    loop {
+                        match self.next_line().await {
+                            Ok(Some(command)) if command == "command_list_end" => break,
+                            Ok(Some(command)) => list.push(command),
+                            _ => return,
+                        }
+                    }
+                    self.reply_list(&list, line == "command_list_ok_begin")
+                },
+                // Client is not idle, so there is nothing to interrupt.
+                "noidle" => continue,
+                _ if self.hangs(&line) => {
+                    while let Ok(Some(_)) = self.next_line().await {}
+                    return;
+                },
+                _ if line == "idle" || line.starts_with("idle ") => {
+                    let (_, args) = parse_command(&line);
+                    match self.idle(&args, &mut seen_changes).await {
+                        Some(response) => response,
+                        None => return,
+                    }
+                },
+                _ => match self.reply(&line, 0) {
+                    Ok(mut response) => {
+                        response.extend(b"OK\n");
+                        response
+                    },
+                    Err(ack) => ack.into_bytes(),
+                },
+            };
+            if self.write(&response).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Wait for changes of `subsystems`, or of anything if it's empty, until `noidle`.
+    /// Returns `None` if the client is gone.
+    async fn idle(&mut self, subsystems: &[String], seen_changes: &mut usize) -> Option<Vec<u8>> {
+        let filter: Vec<Subsystem> =
+            subsystems.iter().filter_map(|name| Subsystem::from_name(name)).collect();
+        loop {
+            let listener = self.shared.changed.listen();
+            let mut changed: Vec<Subsystem> = {
+                let changes = self.shared.changes.lock().unwrap();
+                let changed = changes[*seen_changes..].to_vec();
+                *seen_changes = changes.len();
+                changed
+            };
+            changed.retain(|subsystem| filter.is_empty() || filter.contains(subsystem));
+            changed.dedup();
+
+            let is_interrupted = if changed.is_empty() {
+                let line = smol::future::or(async { Some(self.next_line().await) }, async {
+                    listener.await;
+                    None
+                })
+                .await;
+                match line {
+                    Some(Ok(Some(line))) if line == "noidle" => true,
+                    Some(_) => return None,
+                    None => false,
+                }
+            } else {
+                false
+            };
+
+            if is_interrupted || !changed.is_empty() {
+                let mut response = String::new();
+                for subsystem in changed {
+                    response.push_str(&format!("changed: {}\n", subsystem.name()));
+                }
+                response.push_str("OK\n");
+                return Some(response.into_bytes());
+            }
+        }
+    }
+
+    /// Whether the `command` is never answered, see [`Reply::Hang`].
+    fn hangs(&self, command: &str) -> bool {
+        let (name, _) = parse_command(command);
+        matches!(self.shared.state.lock().unwrap().replies.get(&name), Some(Reply::Hang))
+    }
+
+    /// Reply to every command of a command list, stopping at the first failed one.
+    fn reply_list(&self, list: &[String], ok_after_each: bool) -> Vec<u8> {
+        let mut response = Vec::new();
+        for (index, command) in list.iter().enumerate() {
+            match self.reply(command, index) {
+                Ok(reply) => {
+                    response.extend(reply);
+                    if ok_after_each {
+                        response.extend(b"list_OK\n");
+                    }
+                },
+                Err(ack) => {
+                    response.extend(ack.into_bytes());
+                    return response;
+                },
+            }
+        }
+        response.extend(b"OK\n");
+        response
+    }
+
+    /// Body of the reply to a single `command`, or the `ACK` line if it fails.
+    fn reply(&self, command: &str, index: usize) -> Result<Vec<u8>, String> {
+        let (name, args) = parse_command(command);
+        let mut state = self.shared.state.lock().unwrap();
+        let ack =
+            |code: u32, message: String| format!("ACK [{code}@{index}] {{{name}}} {message}\n");
+        let no_song = || ack(50, "No such song".to_owned());
+        let bad_index = || ack(2, "Bad song index".to_owned());
+        let len = state.queue.len();
+        let arg = |index: usize| args.get(index).and_then(|arg| arg.parse::<usize>().ok());
+        let range = |index: usize| {
+            let range = args.get(index).and_then(|arg| parse_range(arg));
+            range.filter(|range| range.start < range.end && range.end <= len)
+        };
+        let id = |index: usize| arg(index).and_then(|id| state.position(id as u32));
+        if state.not_commands.contains(&name) {
+            return Err(ack(4, format!("you don't have permission for \"{name}\"")));
+        }
+
+        let pairs = match name.as_str() {
+            "ping" => Vec::new(),
+            "password" if state.password.as_ref() == args.first() => Vec::new(),
+            "password" => return Err(ack(3, "incorrect password".to_owned())),
+            "status" => {
+                let mut status = state.status.clone();
+                status.push(("playlist".to_owned(), state.queue_version.to_string()));
+                status.push(("playlistlength".to_owned(), state.queue.len().to_string()));
+                status
+            },
+            "currentsong" => {
+                state.current_song.and_then(|pos| state.queue.get(pos)).cloned().unwrap_or_default()
+            },
+            "playlistinfo" => {
+                let range = match args.first().map(|range| range.split_once(':')) {
+                    Some(Some((start, end))) => {
+                        start.parse().unwrap_or(0)..end.parse().unwrap_or(usize::MAX)
+                    },
+                    Some(None) => {
+                        let pos = args[0].parse().unwrap_or(usize::MAX);
+                        pos..pos.saturating_add(1)
+                    },
+                    None => 0..usize::MAX,
+                };
+                let end = range.end.min(state.queue.len());
+                state.queue[range.start.min(end)..end].concat()
+            },
+            "plchanges" => {
+                let version = args.first().and_then(|version| version.parse().ok()).unwrap_or(0);
+                state.queue_changes(version).flatten().cloned().collect()
+            },
+            "plchangesposid" => {
+                let version = args.first().and_then(|version| version.parse().ok()).unwrap_or(0);
+                state
+                    .queue_changes(version)
+                    .flat_map(|song| {
+                        let pos = song_value(song, "Pos").unwrap_or_default().to_string();
+                        let id = song_value(song, "Id").unwrap_or_default().to_string();
+                        [("cpos".to_owned(), pos), ("Id".to_owned(), id)]
+                    })
+                    .collect()
+            },
+            "playlistid" => match id(0) {
+                Some(pos) => state.queue[pos].clone(),
+                None => return Err(no_song()),
+            },
+            "addid" => match arg(1).or(args.get(1).is_none().then_some(len)) {
+                Some(pos) if pos <= len => {
+                    let id = state.insert_song(pos, &args[0], &[]);
+                    pairs(&[("Id", &id.to_string())])
+                },
+                _ => return Err(bad_index()),
+            },
+            "deleteid" => match id(0) {
+                Some(pos) => {
+                    state.delete_song(pos);
+                    Vec::new()
+                },
+                None => return Err(no_song()),
+            },
+            "delete" => match range(0) {
+                Some(range) => {
+                    state.delete_songs(range);
+                    Vec::new()
+                },
+                None => return Err(bad_index()),
+            },
+            "moveid" => match (id(0), arg(1)) {
+                (Some(from), Some(to)) if to < len => {
+                    state.move_song(from, to);
+                    Vec::new()
+                },
+                (None, _) => return Err(no_song()),
+                _ => return Err(bad_index()),
+            },
+            "move" => match (range(0), arg(1)) {
+                (Some(range), Some(to)) if to + range.len() <= len => {
+                    state.move_songs(range, to);
+                    Vec::new()
+                },
+                _ => return Err(bad_index()),
+            },
+            "prioid" | "prio" => {
+                let Some(prio) = arg(0).filter(|&prio| prio <= u8::MAX as usize) else {
+                    return Err(ack(2, "Priority out of range".to_owned()));
+                };
+                let mut positions = Vec::new();
+                for index in 1..args.len() {
+                    match name.as_str() {
+                        "prioid" => positions.push(id(index).ok_or_else(no_song)?),
+                        _ => positions.extend(range(index).ok_or_else(bad_index)?),
+                    }
+                }
+                for pos in positions {
+                    state.set_priority(pos, prio as u8);
+                }
+                Vec::new()
+            },
+            "clear" => {
+                state.clear();
+                Vec::new()
+            },
+            "shuffle" => {
+                state.shuffle();
+                Vec::new()
+            },
+            "commands" => named_pairs("command", &state.commands),
+            "notcommands" => named_pairs("command", &state.not_commands),
+            "tagtypes" => named_pairs("tagtype", &state.tag_types),
+            _ => match state.replies.get(&name) {
+                Some(Reply::Pairs(pairs)) => pairs.clone(),
+                Some(Reply::Binary(pairs, binary)) => {
+                    let mut response = encode_pairs(pairs);
+                    response.extend(format!("binary: {}\n", binary.len()).into_bytes());
+                    response.extend(binary);
+                    response.push(b'\n');
+                    return Ok(response);
+                },
+                Some(Reply::Ack { code, message }) => return Err(ack(*code, message.clone())),
+                Some(Reply::Raw(lines)) => return Ok(lines.clone().into_bytes()),
+                // Hung commands in a list are answered, since it's not worth the hassle.
+                Some(Reply::Hang) => Vec::new(),
+                None => return Err(ack(5, format!("unknown command \"{name}\""))),
+            },
+        };
+
+        Ok(encode_pairs(&pairs))
+    }
+
+    /// Read the next line and record it. Returns `None` if the client is gone, or every
+    /// client is disconnected by [`MockServer::disconnect_all`].
+    async fn next_line(&mut self) -> io::Result<Option<String>> {
+        let disconnect = self.shared.disconnect.listen();
+        if self.shared.disconnects.load(Ordering::SeqCst) != self.disconnects {
+            return Ok(None);
+        }
+        let mut line = String::new();
+        let read = smol::future::or(self.stream.read_line(&mut line), async {
+            disconnect.await;
+            Ok(0)
+        })
+        .await?;
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end_matches('\n').to_owned();
+        self.shared.received.lock().unwrap().push(line.clone());
+        Ok(Some(line))
+    }
+
+    async fn write(&mut self, response: &[u8]) -> io::Result<()> {
+        self.stream.get_mut().write_all(response).await?;
+        self.stream.get_mut().flush().await
+    }
+}
+
+/// Positions in a range argument, like `3:7`, or in a single position one.
+fn parse_range(arg: &str) -> Option<Range<usize>> {
+    match arg.split_once(':') {
+        Some((start, end)) => Some(start.parse().ok()?..end.parse().ok()?),
+        None => arg.parse().ok().map(|pos: usize| pos..pos + 1),
+    }
+}
+
+/// Numeric value of the `key` of a `song`.
+fn song_value(song: &[(String, String)], key: &str) -> Option<u32> {
+    song.iter().find(|(k, _)| k == key).and_then(|(_, value)| value.parse().ok())
+}
+
+fn named_pairs(key: &str, values: &[String]) -> Vec<(String, String)> {
+    values.iter().map(|value| (key.to_owned(), value.clone())).collect()
+}
+
+fn encode_pairs(pairs: &[(String, String)]) -> Vec<u8> {
+    pairs.iter().flat_map(|(key, value)| format!("{key}: {value}\n").into_bytes()).collect()
+}
+
+/// Split a command line into its name and unquoted arguments.
+fn parse_command(line: &str) -> (String, Vec<String>) {
+    let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut args = Vec::new();
+    let mut chars = rest.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' => {
+                chars.next();
+            },
+            '"' => {
+                chars.next();
+                let mut arg = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => arg.extend(chars.next()),
+                        c => arg.push(c),
+                    }
+                }
+                args.push(arg);
+            },
+            _ => {
+                let mut arg = String::new();
+                while let Some(c) = chars.next_if(|c| *c != ' ') {
+                    arg.push(c);
+                }
+                args.push(arg);
+            },
+        }
+    }
+
+    (name.to_owned(), args)
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    time::{Duration, Instant},
+};
+
+use smol::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use super::{address::MpdStream, trace::ConnectionTrace, MpdError, MpdOptions, MpdResult};
+
+/// Single MPD command with its arguments, which are quoted when sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    name: &'static str,
+    args: Vec<String>,
+}
+
+impl Command {
+    pub fn new(name: &'static str) -> Self {
+        Self { name, args: Vec::new() }
+    }
+
+    /// Append an argument to the command.
+    pub fn arg(mut self, arg: impl ToString) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Name of the command, as used by the MPD protocol.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        for arg in &self.args {
+            f.write_str(" \"")?;
+            for c in arg.chars() {
+                if matches!(c, '"' | '\\') {
+                    f.write_str("\\")?;
+                }
+                write!(f, "{c}")?;
+            }
+            f.write_str("\"")?;
+        }
+        Ok(())
+    }
+}
+
+/// Successful response to a [`Command`]: every `key: value` line, and a binary
+/// chunk if the command returned one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    pub pairs: Vec<(String, String)>,
+    pub binary: Option<Vec<u8>>,
+}
+
+impl Response {
+    /// Value of the first pair with `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Error reported by the MPD server in the `ACK [code@index] {command} message` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+    pub code: u32,
+    /// Index of the failed command in a command list, `0` for a single command.
+    pub index: usize,
+    pub command: String,
+    pub message: String,
+}
+
+impl Ack {
+    /// Parse the `ACK` line, without the `ACK ` prefix.
+    fn parse(line: &str) -> Option<Self> {
+        let (error, rest) = line.strip_prefix('[')?.split_once("] {")?;
+        let (code, index) = error.split_once('@')?;
+        let (command, message) = rest.split_once("} ").unwrap_or((rest.trim_end_matches('}'), ""));
+
+        Some(Self {
+            code: code.parse().ok()?,
+            index: index.parse().ok()?,
+            command: command.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Ack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MPD error {} in {}: {}", self.code, self.command, self.message)
+    }
+}
+
+impl std::error::Error for Ack {}
+
+/// Version of the MPD protocol, as reported by the server greeting.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// Parse the `major.minor.patch` version, where patch is optional.
+    fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.trim().split('.').map(str::parse);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+
+        Some(Self { major, minor, patch })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// How long [`Connection`] waits for the MPD server before failing with
+/// [`MpdError::Timeout`], and how often it checks that the server is alive.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    /// Time to connect, and to send every request.
+    pub write: Duration,
+    /// Time to wait for every line of a response.
+    pub read: Duration,
+    /// Read timeouts of specific commands by name, instead of `read`.
+    pub commands: HashMap<String, Duration>,
+    /// Ping connections which were silent for this long, so MPD doesn't close them after
+    /// its `connection_timeout`, which is a minute by default. `None` disables pings.
+    pub keepalive: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Read timeout of the `command`. `idle` waits for changes as long as it takes,
+    /// so it never times out.
+    pub fn read_for(&self, command: &str) -> Option<Duration> {
+        match command {
+            "idle" => None,
+            _ => Some(self.commands.get(command).copied().unwrap_or(self.read)),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            write: Duration::from_secs(10),
+            read: Duration::from_secs(15),
+            commands: HashMap::new(),
+            keepalive: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Single connection to the MPD server, speaking the raw protocol.
+#[derive(Debug)]
+pub struct Connection {
+    stream: BufReader<MpdStream>,
+    version: Version,
+    timeouts: Timeouts,
+    /// When anything was last sent or received.
+    last_activity: Instant,
+    trace: Option<ConnectionTrace>,
+}
+
+impl Connection {
+    /// Connect to the address from `options` and read the server greeting.
+    pub async fn open(options: &MpdOptions) -> MpdResult<Self> {
+        let timeouts = options.timeouts.clone();
+        let stream = timeout("connect", Some(timeouts.write), options.addr.connect()).await?;
+        let trace = options.trace.as_ref().map(|trace| trace.connection(&options.addr));
+        let mut connection = Self {
+            stream: BufReader::new(stream),
+            version: Version::default(),
+            timeouts,
+            last_activity: Instant::now(),
+            trace,
+        };
+        let greeting = connection.read_line("connect").await?;
+        connection.version = greeting
+            .strip_prefix("OK MPD ")
+            .and_then(Version::parse)
+            .ok_or_else(|| MpdError::Parse(format!("greeting {greeting}")))?;
+
+        Ok(connection)
+    }
+
+    /// Protocol version of the server.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Send the `command` and wait for the whole response.
+    pub async fn execute(&mut self, command: &Command) -> MpdResult<Response> {
+        self.send(command).await?;
+        self.read(command.name()).await
+    }
+
+    /// Send the `command` without waiting for the response, which is read later with
+    /// [`Connection::read`]. Used for `idle`, which is answered only once something changes.
+    pub async fn send(&mut self, command: &Command) -> MpdResult<()> {
+        tracing::trace!("MPD command: {command}");
+        self.write(&format!("{command}\n"), command.name()).await
+    }
+
+    /// Read the whole response to the `command` sent with [`Connection::send`].
+    pub async fn read(&mut self, command: &str) -> MpdResult<Response> {
+        self.read_response("OK", command).await
+    }
+
+    /// Wait until the server sends anything, without reading it. Unlike reading, waiting
+    /// can be dropped at any point without losing a part of the response.
+    pub async fn readable(&mut self) -> MpdResult<()> {
+        self.stream.fill_buf().await?;
+        Ok(())
+    }
+
+    /// How long nothing was sent or received over the connection.
+    pub fn silent_for(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Send every command at once in a command list, and wait for a response to each
+    /// of them. Stops at the first failed command, and fails with [`MpdError::Ack`]
+    /// holding the index of the command.
+    pub async fn execute_list(&mut self, commands: &[Command]) -> MpdResult<Vec<Response>> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut request = String::from("command_list_ok_begin\n");
+        for command in commands {
+            tracing::trace!("MPD command in list: {command}");
+            request.push_str(&format!("{command}\n"));
+        }
+        request.push_str("command_list_end\n");
+        self.write(&request, "command_list_ok_begin").await?;
+
+        let mut responses = Vec::with_capacity(commands.len());
+        for command in commands {
+            responses.push(self.read_response("list_OK", command.name()).await?);
+        }
+        self.read_response("OK", "command_list_end").await?;
+
+        Ok(responses)
+    }
+
+    /// Send the `request` for the `command`, which is named in the timeout error.
+    async fn write(&mut self, request: &str, command: &str) -> MpdResult<()> {
+        if let Some(trace) = &self.trace {
+            request.lines().for_each(|line| trace.request(line));
+        }
+        let stream = self.stream.get_mut();
+        timeout(command, Some(self.timeouts.write), async {
+            stream.write_all(request.as_bytes()).await?;
+            stream.flush().await
+        })
+        .await?;
+        self.last_activity = Instant::now();
+
+        Ok(())
+    }
+
+    /// Read lines until `terminator` or `ACK`, collecting pairs and binary data in between.
+    async fn read_response(&mut self, terminator: &str, command: &str) -> MpdResult<Response> {
+        let mut response = Response::default();
+        loop {
+            let line = self.read_line(command).await?;
+            if line == terminator {
+                return Ok(response);
+            }
+            if let Some(ack) = line.strip_prefix("ACK ") {
+                return Err(match Ack::parse(ack) {
+                    Some(ack) => MpdError::Ack(ack),
+                    None => MpdError::Parse(format!("error {ack}")),
+                });
+            }
+
+            // Rest of the response can't be told apart from the next one after a broken line.
+            let Some((key, value)) = line.split_once(": ") else {
+                return Err(MpdError::Protocol(format!("line {line}")));
+            };
+            if key == "binary" {
+                let len: usize = value
+                    .parse()
+                    .map_err(|_| MpdError::Protocol(format!("binary length {value}")))?;
+                let mut binary = vec![0; len + 1];
+                let read_timeout = self.timeouts.read_for(command);
+                timeout(command, read_timeout, self.stream.read_exact(&mut binary)).await?;
+                self.last_activity = Instant::now();
+                binary.truncate(len);
+                if let Some(trace) = &self.trace {
+                    trace.binary(&binary);
+                }
+                response.binary = Some(binary);
+            } else {
+                response.pairs.push((key.to_owned(), value.to_owned()));
+            }
+        }
+    }
+
+    /// Read a single line of the response to `command`, without the trailing newline.
+    async fn read_line(&mut self, command: &str) -> MpdResult<String> {
+        let mut line = String::new();
+        let read_timeout = self.timeouts.read_for(command);
+        if timeout(command, read_timeout, self.stream.read_line(&mut line)).await? == 0 {
+            let error = io::Error::new(io::ErrorKind::UnexpectedEof, "MPD closed the connection");
+            return Err(error.into());
+        }
+        self.last_activity = Instant::now();
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        if let Some(trace) = &self.trace {
+            trace.response(&line);
+        }
+
+        Ok(line)
+    }
+}
+
+/// Wait for the I/O `future` of the `command`, failing with [`MpdError::Timeout`] after
+/// `after`, if it's set.
+async fn timeout<T>(
+    command: &str,
+    after: Option<Duration>,
+    future: impl Future<Output = io::Result<T>>,
+) -> MpdResult<T> {
+    let Some(after) = after else {
+        return Ok(future.await?);
+    };
+    smol::future::or(async { Ok(future.await?) }, async {
+        smol::Timer::after(after).await;
+        Err(MpdError::Timeout { command: command.to_owned(), after })
+    })
+    .await
+}
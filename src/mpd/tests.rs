@@ -0,0 +1,421 @@
+use std::time::Duration;
+
+use super::{
+    mock::{pairs, MockServer, MockState, Reply},
+    Command, CommandList, Connection, ConnectionState, MpdClient, MpdError, MpdOptions, State,
+    Subsystem, Timeouts, Trace, TraceReplay, Version,
+};
+
+/// Fail the test instead of hanging, if `future` takes too long.
+async fn timeout<T>(future: impl std::future::Future<Output = T>) -> T {
+    smol::future::or(future, async {
+        smol::Timer::after(Duration::from_secs(5)).await;
+        panic!("Timed out");
+    })
+    .await
+}
+
+async fn connect(server: &MockServer) -> MpdClient {
+    MpdClient::new(server.address(), None::<&str>).await.expect("Connected to the mock server")
+}
+
+#[test]
+fn reads_version_and_status() {
+    let state = MockState {
+        status: pairs(&[
+            ("volume", "42"),
+            ("repeat", "1"),
+            ("state", "play"),
+            ("song", "1"),
+            ("songid", "2"),
+            ("elapsed", "12.5"),
+        ]),
+        ..Default::default()
+    };
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        assert_eq!(mpd.capabilities().version, Version::new(0, 23, 5));
+
+        let status = mpd.bind().await.unwrap().status().await.unwrap();
+        assert_eq!(status.volume, Some(42));
+        assert!(status.repeat);
+        assert_eq!(status.state, State::Play);
+        assert_eq!(status.song.map(|song| (song.pos, song.id)), Some((1, 2)));
+        assert_eq!(status.elapsed, Some(Duration::from_millis(12500)));
+    }));
+}
+
+#[test]
+fn reads_current_song_and_queue() {
+    let mut state = MockState::default();
+    state.add_song("first.flac", &[("Artist", "Someone"), ("Title", "First")]);
+    state.add_song("second.flac", &[("Title", "Second"), ("Time", "61")]);
+    state.current_song = Some(1);
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        let mut client = mpd.bind().await.unwrap();
+
+        let song = client.current_song().await.unwrap().unwrap();
+        assert_eq!(song.file, "second.flac");
+        assert_eq!(song.duration, Some(Duration::from_secs(61)));
+
+        let queue = client.playlist_info().await.unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue[0].artist.as_deref(), Some("Someone"));
+        assert_eq!(queue[1].place.map(|place| (place.pos, place.id)), Some((1, 2)));
+    }));
+}
+
+#[test]
+fn sends_password_first() {
+    let state = MockState { password: Some("secret".to_owned()), ..Default::default() };
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        MpdClient::new(server.address(), Some("secret")).await.unwrap();
+        assert_eq!(server.received().first().map(String::as_str), Some("password \"secret\""));
+
+        let error = MpdClient::new(server.address(), Some("wrong")).await.unwrap_err();
+        assert!(matches!(error, MpdError::Ack(ack) if ack.code == 3 && ack.command == "password"));
+    }));
+}
+
+#[test]
+fn keeps_connection_after_ack() {
+    let mut state = MockState::default();
+    state.reply("play", Reply::Ack { code: 2, message: "Bad song index".to_owned() });
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        let mut client = mpd.bind().await.unwrap();
+
+        let error = client.play().await.unwrap_err();
+        assert!(error.is_recoverable());
+        assert!(matches!(&error, MpdError::Ack(ack) if ack.code == 2 && ack.command == "play"));
+        assert!(client.status().await.is_ok());
+    }));
+}
+
+#[test]
+fn reconnects_after_broken_response() {
+    let mut state = MockState::default();
+    state.reply("stats", Reply::Raw("garbage\nsongs: 3\n".to_owned()));
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        let mut client = mpd.bind().await.unwrap();
+        let error = client.execute(&Command::new("stats")).await.unwrap_err();
+        assert!(matches!(&error, MpdError::Protocol(line) if line == "line garbage"));
+        assert!(!error.is_recoverable());
+        drop(client);
+        mpd.handle_error(&error);
+
+        // Rest of the broken response is dropped with the connection, and isn't read as
+        // the response to the next command.
+        let connections = || server.received().iter().filter(|line| *line == "commands").count();
+        while connections() < 2 || mpd.connection_state() != ConnectionState::Connected {
+            smol::Timer::after(Duration::from_millis(10)).await;
+        }
+        let response = mpd.bind().await.unwrap().execute(&Command::new("ping")).await.unwrap();
+        assert!(response.pairs.is_empty());
+    }));
+}
+
+#[test]
+fn rejects_not_permitted_commands() {
+    let state = MockState { not_commands: vec!["setvol".to_owned()], ..Default::default() };
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        assert_eq!(
+            mpd.capabilities().unavailable_reason("setvol"),
+            Some("not permitted for the current user")
+        );
+
+        let error = mpd.bind().await.unwrap().set_volume(10).await.unwrap_err();
+        assert!(matches!(error, MpdError::Ack(ack) if ack.code == 4));
+    }));
+}
+
+#[test]
+fn reads_binary_response() {
+    // Binary data may contain anything, including newlines and protocol keywords.
+    let picture = b"\x89PNG\nOK\nACK [5@0] {}\n\x00".to_vec();
+    let mut state = MockState::default();
+    state.reply(
+        "readpicture",
+        Reply::Binary(pairs(&[("size", &picture.len().to_string())]), picture.clone()),
+    );
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        let mut client = mpd.bind().await.unwrap();
+
+        let command = Command::new("readpicture").arg("song.flac").arg(0);
+        let response = client.execute(&command).await.unwrap();
+        assert_eq!(response.get("size"), Some(picture.len().to_string().as_str()));
+        assert_eq!(response.binary, Some(picture));
+        assert!(client.status().await.is_ok());
+        assert!(server.received().contains(&"readpicture \"song.flac\" \"0\"".to_owned()));
+    }));
+}
+
+#[test]
+fn keeps_partition_when_switching_fails() {
+    let state = MockState { password: Some("secret".to_owned()), ..Default::default() };
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = MpdClient::new(server.address(), Some("secret")).await.unwrap();
+        // Connections which are already open stay logged in, but new ones fail.
+        server.update(|state| state.password = Some("changed".to_owned()));
+        assert!(matches!(mpd.switch_partition("other").await, Err(MpdError::Ack(_))));
+        assert_eq!(mpd.partition(), None);
+        assert!(!server.received().iter().any(|line| line.starts_with("partition")));
+        assert!(mpd.bind().await.unwrap().status().await.is_ok());
+    }));
+}
+
+#[test]
+fn batch_reports_failed_command() {
+    let mut state = MockState::default();
+    state.reply("playid", Reply::Pairs(Vec::new()));
+    state.reply("deleteid", Reply::Ack { code: 50, message: "No such song".to_owned() });
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        let mut client = mpd.bind().await.unwrap();
+
+        let list = CommandList::new().play_id(1).delete_id(7).play_id(2);
+        let error = client.batch(&list).await.unwrap_err();
+        assert!(matches!(error, MpdError::Ack(ack) if ack.index == 1 && ack.command == "deleteid"));
+
+        let received = server.received();
+        let start = received.iter().position(|line| line == "command_list_ok_begin").unwrap();
+        assert_eq!(
+            received[start..start + 5],
+            [
+                "command_list_ok_begin",
+                "playid \"1\"",
+                "deleteid \"7\"",
+                "playid \"2\"",
+                "command_list_end"
+            ]
+        );
+        assert!(client.status().await.is_ok());
+    }));
+}
+
+#[test]
+fn notifies_about_changes() {
+    let server = MockServer::start(MockState::default()).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        server.update(|state| state.status = pairs(&[("state", "play")]));
+        let waiting = smol::spawn({
+            let mpd = mpd.clone();
+            async move { mpd.wait_for(&[Subsystem::Player]).await }
+        });
+
+        // Keep notifying, since the idle connection may not be parked yet.
+        smol::future::or(waiting, async {
+            loop {
+                server.notify(Subsystem::Player);
+                smol::Timer::after(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert_eq!(mpd.bind().await.unwrap().status().await.unwrap().state, State::Play);
+    }));
+}
+
+#[test]
+fn reads_idle_answer_after_interrupting() {
+    let server = MockServer::start(MockState::default()).unwrap();
+
+    smol::block_on(timeout(async {
+        let options = MpdOptions {
+            addr: server.address(),
+            password: None,
+            timeouts: Timeouts::default(),
+            trace: None,
+        };
+        let mut connection = Connection::open(&options).await.unwrap();
+        connection.send_idle(&[Subsystem::Player]).await.unwrap();
+        server.notify(Subsystem::Player);
+
+        // Waiting for the answer leaves it unread, so it's read after `noidle` like
+        // keepalive does, even if the server has answered in the meantime.
+        connection.readable().await.unwrap();
+        connection.readable().await.unwrap();
+        assert_eq!(connection.no_idle().await.unwrap(), [Subsystem::Player]);
+        assert!(connection.execute(&Command::new("ping")).await.unwrap().pairs.is_empty());
+    }));
+}
+
+#[test]
+fn reconnects_after_disconnect() {
+    let server = MockServer::start(MockState::default()).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        // Wait for the idle connection to be served, so it's dropped too.
+        while !server.received().iter().any(|line| line == "idle") {
+            smol::Timer::after(Duration::from_millis(10)).await;
+        }
+        server.disconnect_all();
+
+        // Capabilities are queried once for every successful connection.
+        let connections = || server.received().iter().filter(|line| *line == "commands").count();
+        while connections() < 2 || mpd.connection_state() != ConnectionState::Connected {
+            smol::Timer::after(Duration::from_millis(10)).await;
+        }
+        assert!(mpd.bind().await.unwrap().status().await.is_ok());
+    }));
+}
+
+#[test]
+fn times_out_hung_command() {
+    let mut state = MockState::default();
+    state.reply("update", Reply::Hang);
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let timeouts = Timeouts {
+            commands: [("update".to_owned(), Duration::from_millis(100))].into(),
+            ..Default::default()
+        };
+        let options = MpdOptions { addr: server.address(), password: None, timeouts, trace: None };
+        let mpd = MpdClient::with_options(options).await.unwrap();
+
+        let mut client = mpd.bind().await.unwrap();
+        let error = client.execute(&Command::new("update")).await.unwrap_err();
+        assert!(matches!(&error, MpdError::Timeout { command, .. } if command == "update"));
+        assert!(!error.is_recoverable());
+    }));
+}
+
+#[test]
+fn opens_single_mailbox_for_concurrent_requests() {
+    let mut state = MockState::default();
+    for command in ["subscribe", "readmessages"] {
+        state.reply(command, Reply::Pairs(Vec::new()));
+    }
+    let server = MockServer::start(state).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        let (first, second) = smol::future::zip(mpd.subscribe("a"), mpd.subscribe("b")).await;
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(mpd.subscriptions(), ["a", "b"]);
+
+        // Every mailbox connection waits in its own `idle`, interrupted only by requests.
+        let parked = || {
+            let received = server.received();
+            let count = |line: &str| received.iter().filter(|received| *received == line).count();
+            count("idle \"message\"") - count("noidle")
+        };
+        while parked() == 0 {
+            smol::Timer::after(Duration::from_millis(10)).await;
+        }
+        smol::Timer::after(Duration::from_millis(100)).await;
+        assert_eq!(parked(), 1);
+    }));
+}
+
+#[test]
+fn pings_silent_connections() {
+    let server = MockServer::start(MockState::default()).unwrap();
+
+    smol::block_on(timeout(async {
+        let keepalive = Some(Duration::from_millis(50));
+        let timeouts = Timeouts { keepalive, ..Default::default() };
+        let options = MpdOptions { addr: server.address(), password: None, timeouts, trace: None };
+        let _mpd = MpdClient::with_options(options).await.unwrap();
+
+        // Command connection is pinged, and idle one is interrupted.
+        let received = |command: &str| server.received().iter().any(|line| line == command);
+        while !received("ping") || !received("noidle") {
+            smol::Timer::after(Duration::from_millis(10)).await;
+        }
+    }));
+}
+
+#[test]
+fn replays_recorded_trace() {
+    let picture = b"\x89PNG\nOK\n".to_vec();
+    let mut state = MockState {
+        password: Some("secret".to_owned()),
+        status: pairs(&[("volume", "42"), ("state", "play")]),
+        ..Default::default()
+    };
+    state.add_song("first.flac", &[("Title", "First")]);
+    state.reply("playid", Reply::Pairs(Vec::new()));
+    state.reply(
+        "readpicture",
+        Reply::Binary(pairs(&[("size", &picture.len().to_string())]), picture.clone()),
+    );
+    let server = MockServer::start(state).unwrap();
+    let path = std::env::temp_dir().join(format!("minisong-test-{}.trace", std::process::id()));
+
+    // Same session, first against the server and then against its replay.
+    let session = |options: MpdOptions| async {
+        let mpd = MpdClient::with_options(options).await.unwrap();
+        let mut client = mpd.bind().await.unwrap();
+        let status = client.status().await.unwrap();
+        let queue = client.playlist_info().await.unwrap();
+        let command = Command::new("readpicture").arg("first.flac").arg(0);
+        let binary = client.execute(&command).await.unwrap().binary;
+        let list = client.batch(&CommandList::new().play_id(1)).await;
+        mpd.close();
+        (status.volume, queue.len(), binary, list.is_ok())
+    };
+
+    smol::block_on(timeout(async {
+        let trace = std::sync::Arc::new(Trace::create(&path).unwrap());
+        let password = Some("secret".to_owned());
+        let options = MpdOptions {
+            addr: server.address(),
+            password,
+            timeouts: Timeouts::default(),
+            trace: Some(trace.clone()),
+        };
+        let recorded = session(options).await;
+        assert_eq!(recorded, (Some(42), 1, Some(picture.clone()), true));
+        trace.flush();
+
+        let recording = std::fs::read_to_string(&path).unwrap();
+        assert!(!recording.contains("secret"));
+
+        let replay = TraceReplay::start(&path).unwrap();
+        let timeouts = Timeouts::default();
+        let options = MpdOptions { addr: replay.address(), password: None, timeouts, trace: None };
+        assert_eq!(session(options).await, recorded);
+    }));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn connects_over_unix_socket() {
+    let path = std::env::temp_dir().join(format!("minisong-test-{}.socket", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let server = MockServer::start_unix(&path, MockState::default()).unwrap();
+
+    smol::block_on(timeout(async {
+        let mpd = connect(&server).await;
+        assert!(mpd.bind().await.unwrap().status().await.is_ok());
+    }));
+    let _ = std::fs::remove_file(&path);
+}
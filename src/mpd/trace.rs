@@ -0,0 +1,411 @@
+//! Recording of the raw MPD protocol, and its replay by a fake server.
+//!
+//! Trace is a text file, with a line for every event of every connection:
+//!
+//! ```text
+//! # minisong protocol trace, started at 2025-01-01T12:00:00+00:00
+//! 0.000120 1 open localhost:6600
+//! 0.001003 1 < OK MPD 0.23.5
+//! 0.001140 1 > status
+//! 0.001518 1 < volume: 50
+//! 0.001533 1 < OK
+//! 0.002001 1 <binary 89504e47
+//! ```
+//!
+//! Each line has seconds since the start, connection number, and the event: `open` with
+//! the address, `>` request line, `<` response line, or `<binary` chunk in hex.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, BufReader as StdBufReader, BufWriter, Write},
+    net::{Ipv4Addr, TcpListener},
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use smol::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    Async, Task,
+};
+
+use super::{address::MpdStream, MpdAddress};
+
+/// Recorder of every request and response of [`super::MpdClient`], see the module docs.
+#[derive(Debug)]
+pub struct Trace {
+    start: Instant,
+    /// Lines go to a thread which writes them, so connections never wait for the file.
+    records: Option<Sender<Record>>,
+    writer: Option<JoinHandle<()>>,
+    next_connection: AtomicU32,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let started = chrono::Local::now().to_rfc3339();
+        writeln!(file, "# minisong protocol trace, started at {started}")?;
+        file.flush()?;
+        let (records, received) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("protocol trace".to_owned())
+            .spawn(move || write_records(file, received))?;
+
+        Ok(Self {
+            start: Instant::now(),
+            records: Some(records),
+            writer: Some(writer),
+            next_connection: AtomicU32::new(1),
+        })
+    }
+
+    /// Start recording a new connection to `addr`.
+    pub(super) fn connection(self: &Arc<Self>, addr: &MpdAddress) -> ConnectionTrace {
+        let id = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let connection = ConnectionTrace { trace: self.clone(), id };
+        connection.record("open", &addr.to_string());
+        connection
+    }
+
+    /// Wait until every line recorded so far is in the file.
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.send(Record::Flush(done)) {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// Pass the `record` to the writer thread. Returns `false` if the thread is gone.
+    fn send(&self, record: Record) -> bool {
+        self.records.as_ref().is_some_and(|records| records.send(record).is_ok())
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        // Writer stops once the channel is closed, after writing every line left.
+        self.records.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Message to the writer thread of a [`Trace`].
+#[derive(Debug)]
+enum Record {
+    Line(String),
+    /// Flush the file, and report it when done.
+    Flush(Sender<()>),
+}
+
+/// Write `records` to the trace `file` until every sender is gone. The file is flushed
+/// once there are no more lines for now, rather than after each of them.
+fn write_records(mut file: BufWriter<File>, records: Receiver<Record>) {
+    let report = |result: io::Result<()>| {
+        if let Err(error) = result {
+            tracing::warn!("Writing MPD protocol trace: {error}");
+        }
+    };
+    let mut waiting = Vec::new();
+    let mut next = records.recv().ok();
+    while let Some(record) = next {
+        match record {
+            Record::Line(line) => report(writeln!(file, "{line}")),
+            Record::Flush(done) => waiting.push(done),
+        }
+        next = records.try_recv().ok();
+        if next.is_none() {
+            report(file.flush());
+            for done in waiting.drain(..) {
+                let _ = done.send(());
+            }
+            next = records.recv().ok();
+        }
+    }
+}
+
+/// Recorder of a single connection, see [`Trace`].
+#[derive(Debug)]
+pub(super) struct ConnectionTrace {
+    trace: Arc<Trace>,
+    id: u32,
+}
+
+impl ConnectionTrace {
+    pub(super) fn request(&self, line: &str) {
+        // Traces are meant to be shared, so the password never gets there.
+        if line.starts_with("password ") {
+            self.record(">", "password \"<redacted>\"");
+        } else {
+            self.record(">", line);
+        }
+    }
+
+    pub(super) fn response(&self, line: &str) {
+        self.record("<", line);
+    }
+
+    pub(super) fn binary(&self, chunk: &[u8]) {
+        let hex = chunk.iter().fold(String::with_capacity(chunk.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+        self.record("<binary", &hex);
+    }
+
+    fn record(&self, event: &str, payload: &str) {
+        let elapsed = self.trace.start.elapsed().as_secs_f64();
+        let line = format!("{elapsed:.6} {} {event} {payload}", self.id);
+        self.trace.send(Record::Line(line));
+    }
+}
+
+/// Part of a recorded response.
+#[derive(Debug, Clone)]
+enum Chunk {
+    Line(String),
+    Binary(Vec<u8>),
+}
+
+/// Recorded response to a request, and how long it took.
+#[derive(Debug, Clone)]
+struct Exchange {
+    delay: Duration,
+    response: Vec<Chunk>,
+}
+
+/// Recorded responses of every connection, by their requests in the recorded order.
+#[derive(Debug, Default)]
+struct Recording {
+    greeting: String,
+    exchanges: HashMap<String, VecDeque<Exchange>>,
+    /// Last response to every request, repeated when recorded ones run out.
+    last: HashMap<String, Exchange>,
+}
+
+impl Recording {
+    fn parse(path: &Path) -> io::Result<Self> {
+        let invalid = |number: usize, message: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("trace line {number}: {message}"))
+        };
+
+        // Requests which are waiting for a response, by connection.
+        let mut pending: HashMap<u32, (Vec<String>, f64)> = HashMap::new();
+        let mut recording = Recording::default();
+        let mut last_exchange: HashMap<u32, (String, usize)> = HashMap::new();
+        for (index, line) in StdBufReader::new(File::open(path)?).lines().enumerate() {
+            let (number, line) = (index + 1, line?);
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(4, ' ');
+            let (Some(time), Some(connection), Some(event)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid(number, "missing fields"));
+            };
+            let time: f64 = time.parse().map_err(|_| invalid(number, "invalid time"))?;
+            let connection: u32 =
+                connection.parse().map_err(|_| invalid(number, "invalid connection"))?;
+            let payload = parts.next().unwrap_or_default();
+
+            let chunk = match event {
+                "open" => continue,
+                // Interrupted `idle` gets its response after `noidle`, so it's kept pending.
+                ">" if payload == "noidle" => continue,
+                ">" => {
+                    let (requests, start) = pending.entry(connection).or_default();
+                    if last_exchange.remove(&connection).is_some() || requests.is_empty() {
+                        requests.clear();
+                    }
+                    requests.push(payload.to_owned());
+                    *start = time;
+                    continue;
+                },
+                "<" => Chunk::Line(payload.to_owned()),
+                "<binary" => Chunk::Binary(decode_hex(payload).ok_or_else(|| {
+                    invalid(number, "invalid binary chunk")
+                })?),
+                _ => return Err(invalid(number, "unknown event")),
+            };
+
+            match (pending.get_mut(&connection), last_exchange.get(&connection)) {
+                // Continuation of the last response.
+                (_, Some((request, index))) => {
+                    let exchanges = recording.exchanges.get_mut(request).expect("Recorded");
+                    exchanges[*index].response.push(chunk);
+                },
+                // First line of a response.
+                (Some((requests, start)), None) if !requests.is_empty() => {
+                    let request = requests.join("\n");
+                    let delay = Duration::from_secs_f64((time - *start).max(0.0));
+                    let exchanges = recording.exchanges.entry(request.clone()).or_default();
+                    exchanges.push_back(Exchange { delay, response: vec![chunk] });
+                    last_exchange.insert(connection, (request, exchanges.len() - 1));
+                },
+                // Greeting, before any request.
+                _ => match chunk {
+                    Chunk::Line(greeting) if recording.greeting.is_empty() => {
+                        recording.greeting = greeting;
+                    },
+                    _ => {},
+                },
+            }
+        }
+
+        if recording.greeting.is_empty() {
+            return Err(invalid(0, "no server greeting"));
+        }
+        Ok(recording)
+    }
+
+    /// Take the next recorded response to `request`.
+    fn take(&mut self, request: &str) -> Option<Exchange> {
+        match self.exchanges.get_mut(request).and_then(VecDeque::pop_front) {
+            Some(exchange) => {
+                self.last.insert(request.to_owned(), exchange.clone());
+                Some(exchange)
+            },
+            // Events reported by `idle` happened only once.
+            None if request.starts_with("idle") => None,
+            None => self.last.get(request).cloned(),
+        }
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Fake MPD server, which answers requests with responses recorded in a [`Trace`].
+///
+/// Responses are matched to requests by their text, in the recorded order. Responses to
+/// `idle` are delayed like they were in the recording, so server side changes happen
+/// at the same time.
+pub struct TraceReplay {
+    address: MpdAddress,
+    _task: Task<()>,
+}
+
+impl TraceReplay {
+    /// Load the trace from `path`, and start serving it on a random local TCP port.
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let recording = Arc::new(Mutex::new(Recording::parse(path)?));
+        let listener = Async::<TcpListener>::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.get_ref().local_addr()?.port();
+
+        let task = smol::spawn(async move {
+            // Connection tasks are cancelled with the server, when this one is dropped.
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                let stream = BufReader::new(MpdStream::Tcp(stream));
+                connections.push(smol::spawn(Self::serve(stream, recording.clone())));
+            }
+        });
+
+        let address = MpdAddress::Tcp { host: Ipv4Addr::LOCALHOST.to_string(), port };
+        Ok(Self { address, _task: task })
+    }
+
+    pub fn address(&self) -> MpdAddress {
+        self.address.clone()
+    }
+
+    async fn serve(mut stream: BufReader<MpdStream>, recording: Arc<Mutex<Recording>>) {
+        let result: io::Result<()> = async {
+            let greeting = format!("{}\n", recording.lock().unwrap().greeting);
+            stream.get_mut().write_all(greeting.as_bytes()).await?;
+
+            while let Some(request) = Self::read_request(&mut stream).await? {
+                // Like MPD, ignore `noidle` which came too late.
+                if request == "noidle" {
+                    continue;
+                }
+                let exchange = recording.lock().unwrap().take(&request);
+                let response = match exchange {
+                    Some(exchange) if request.starts_with("idle") => {
+                        let noidle = async { Self::read_request(&mut stream).await.map(Some) };
+                        let changes = async {
+                            smol::Timer::after(exchange.delay).await;
+                            Ok(None)
+                        };
+                        match smol::future::or(noidle, changes).await? {
+                            Some(None) => return Ok(()),
+                            Some(Some(_)) => vec![Chunk::Line("OK".to_owned())],
+                            None => exchange.response,
+                        }
+                    },
+                    Some(exchange) => exchange.response,
+                    // Nothing happens anymore, so `idle` waits until `noidle`.
+                    None if request.starts_with("idle") => {
+                        if Self::read_request(&mut stream).await?.is_none() {
+                            return Ok(());
+                        }
+                        vec![Chunk::Line("OK".to_owned())]
+                    },
+                    None => {
+                        let name = request.split(' ').next().unwrap_or_default();
+                        vec![Chunk::Line(format!("ACK [5@0] {{{name}}} not in the trace"))]
+                    },
+                };
+
+                let mut bytes = Vec::new();
+                for chunk in response {
+                    match chunk {
+                        Chunk::Line(line) => bytes.extend(line.into_bytes()),
+                        Chunk::Binary(binary) => bytes.extend(binary),
+                    }
+                    bytes.push(b'\n');
+                }
+                stream.get_mut().write_all(&bytes).await?;
+                stream.get_mut().flush().await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(error) = result {
+            tracing::warn!("Replaying MPD protocol trace: {error}");
+        }
+    }
+
+    /// Read a single request, or a whole command list, or `None` if the client is gone.
+    async fn read_request(stream: &mut BufReader<MpdStream>) -> io::Result<Option<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end_matches('\n').to_owned();
+            let is_list = lines.first().is_some_and(|first: &String| {
+                first == "command_list_begin" || first == "command_list_ok_begin"
+            }) || line == "command_list_begin"
+                || line == "command_list_ok_begin";
+            let is_end = line == "command_list_end";
+            // Passwords are not recorded, and every one of them is accepted.
+            let line = if line.starts_with("password ") {
+                "password \"<redacted>\"".to_owned()
+            } else {
+                line
+            };
+            lines.push(line);
+            if !is_list || is_end {
+                return Ok(Some(lines.join("\n")));
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use super::MpdResult;
+
+/// Playback state of the MPD player.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Play,
+    Pause,
+    #[default]
+    Stop,
+}
+
+/// Single mode of the MPD player.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Single {
+    #[default]
+    Off,
+    On,
+    /// Stop after the current song, and then turn the single mode off.
+    Oneshot,
+}
+
+impl Single {
+    /// Name of the mode, as used by the MPD protocol.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Single::Off => "0",
+            Single::On => "1",
+            Single::Oneshot => "oneshot",
+        }
+    }
+}
+
+/// Place of a song in the queue.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePlace {
+    pub id: u32,
+    pub pos: u32,
+    pub prio: u8,
+}
+
+/// MPD player status, as returned by `status`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Status {
+    /// Volume in percents, or `None` if there is no mixer.
+    pub volume: Option<u8>,
+    pub repeat: bool,
+    pub random: bool,
+    pub single: Single,
+    pub consume: bool,
+    pub queue_version: u32,
+    pub queue_len: u32,
+    pub state: State,
+    pub song: Option<QueuePlace>,
+    pub elapsed: Option<Duration>,
+    pub duration: Option<Duration>,
+    /// Partition of the connection, or `None` if the server has no partitions.
+    pub partition: Option<String>,
+}
+
+impl Status {
+    pub fn from_pairs(pairs: &[(String, String)]) -> MpdResult<Self> {
+        let mut status = Status::default();
+        let mut song = QueuePlace::default();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "volume" => status.volume = value.parse::<i8>()?.try_into().ok(),
+                "repeat" => status.repeat = value == "1",
+                "random" => status.random = value == "1",
+                "single" => {
+                    status.single = match value.as_str() {
+                        "1" => Single::On,
+                        "oneshot" => Single::Oneshot,
+                        _ => Single::Off,
+                    }
+                },
+                "consume" => status.consume = value != "0",
+                "playlist" => status.queue_version = value.parse()?,
+                "playlistlength" => status.queue_len = value.parse()?,
+                "state" => {
+                    status.state = match value.as_str() {
+                        "play" => State::Play,
+                        "pause" => State::Pause,
+                        _ => State::Stop,
+                    }
+                },
+                "song" => song.pos = value.parse()?,
+                "songid" => {
+                    song.id = value.parse()?;
+                    status.song = Some(song);
+                },
+                "elapsed" => status.elapsed = Some(parse_duration(value)?),
+                "duration" => status.duration = Some(parse_duration(value)?),
+                "partition" => status.partition = Some(value.clone()),
+                _ => {},
+            }
+        }
+        // Position may come after the id, so fix up the place only when everything is read.
+        status.song = status.song.map(|place| QueuePlace { pos: song.pos, ..place });
+
+        Ok(status)
+    }
+}
+
+/// Song, either from the queue or from the database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Song {
+    pub file: String,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    /// Place in the queue, if the song is from the queue.
+    pub place: Option<QueuePlace>,
+    /// Every tag of the song, including the ones with separate fields.
+    pub tags: Vec<(String, String)>,
+}
+
+impl Song {
+    /// Parse a list of songs, each of them starts with the `file` key.
+    pub fn list_from_pairs(pairs: &[(String, String)]) -> MpdResult<Vec<Self>> {
+        let mut songs = Vec::new();
+        let mut start = 0;
+        for (index, (key, _)) in pairs.iter().enumerate().skip(1) {
+            if key == "file" {
+                songs.push(Self::from_pairs(&pairs[start..index])?);
+                start = index;
+            }
+        }
+        if start < pairs.len() {
+            songs.push(Self::from_pairs(&pairs[start..])?);
+        }
+
+        Ok(songs)
+    }
+
+    pub fn from_pairs(pairs: &[(String, String)]) -> MpdResult<Self> {
+        let mut song = Song::default();
+        let mut place = QueuePlace::default();
+        let mut in_queue = false;
+        for (key, value) in pairs {
+            match key.as_str() {
+                "file" => song.file = value.clone(),
+                "duration" => song.duration = Some(parse_duration(value)?),
+                "Time" if song.duration.is_none() => {
+                    song.duration = Some(parse_duration(value)?);
+                },
+                "Pos" => {
+                    place.pos = value.parse()?;
+                    in_queue = true;
+                },
+                "Id" => {
+                    place.id = value.parse()?;
+                    in_queue = true;
+                },
+                "Prio" => place.prio = value.parse()?,
+                "Last-Modified" | "Added" | "Format" | "Range" => {},
+                tag => {
+                    match tag {
+                        "Artist" if song.artist.is_none() => song.artist = Some(value.clone()),
+                        "Title" if song.title.is_none() => song.title = Some(value.clone()),
+                        "Album" if song.album.is_none() => song.album = Some(value.clone()),
+                        _ => {},
+                    }
+                    song.tags.push((key.clone(), value.clone()));
+                },
+            }
+        }
+        song.place = in_queue.then_some(place);
+
+        Ok(song)
+    }
+
+    /// First value of the `tag`, case insensitive.
+    pub fn tag(&self, tag: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(tag))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Audio output, as returned by `outputs`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub name: String,
+    pub plugin: String,
+    pub enabled: bool,
+}
+
+impl Output {
+    /// Parse a list of outputs, each of them starts with the `outputid` key.
+    pub fn list_from_pairs(pairs: &[(String, String)]) -> MpdResult<Vec<Self>> {
+        let mut outputs = Vec::new();
+        for (key, value) in pairs {
+            match (key.as_str(), outputs.last_mut()) {
+                ("outputid", _) => outputs.push(Output::default()),
+                ("outputname", Some(output)) => output.name = value.clone(),
+                ("plugin", Some(output)) => output.plugin = value.clone(),
+                ("outputenabled", Some(output)) => output.enabled = value == "1",
+                _ => {},
+            }
+        }
+
+        Ok(outputs)
+    }
+}
+
+/// Message sent by a client to a channel, as returned by `readmessages`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    pub text: String,
+}
+
+impl Message {
+    /// Parse a list of messages, each of them is a `channel` and a `message` pair.
+    pub fn list_from_pairs(pairs: &[(String, String)]) -> Vec<Self> {
+        let mut messages: Vec<Message> = Vec::new();
+        for (key, value) in pairs {
+            match (key.as_str(), messages.last_mut()) {
+                ("channel", _) => {
+                    messages.push(Message { channel: value.clone(), ..Default::default() })
+                },
+                ("message", Some(message)) => message.text = value.clone(),
+                _ => {},
+            }
+        }
+
+        messages
+    }
+}
+
+/// Parse a duration in seconds with an optional fractional part.
+fn parse_duration(value: &str) -> MpdResult<Duration> {
+    Ok(Duration::try_from_secs_f64(value.parse()?)?)
+}
@@ -0,0 +1,293 @@
+use iocraft::prelude::*;
+
+use crate::{
+    app::AppContext,
+    components::VirtualList,
+    mpd::{Feature, Output, Subsystem},
+};
+
+/// Server partitions and outputs for [`PartitionsScreen`].
+#[derive(Debug, Default, Clone)]
+struct Partitions {
+    names: Vec<String>,
+    current: Option<String>,
+    outputs: Vec<Output>,
+}
+
+/// List of [`PartitionsScreen`] which has the keyboard focus.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    #[default]
+    Partitions,
+    Outputs,
+}
+
+/// Actions for [`PartitionsScreen`], switching is done separately for every connection.
+#[derive(Debug, Clone)]
+enum Action {
+    Create(String),
+    Delete(String),
+    /// Move the output to the current partition.
+    MoveOutput(String),
+}
+
+impl Action {
+    /// MPD command used by the action, to check if it's allowed.
+    fn command(&self) -> &'static str {
+        match self {
+            Action::Create(_) => "newpartition",
+            Action::Delete(_) => "delpartition",
+            Action::MoveOutput(_) => "moveoutput",
+        }
+    }
+}
+
+/// Switcher for MPD partitions, each with its own queue, player and outputs.
+#[component]
+pub fn PartitionsScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let ctx = hooks.use_context::<AppContext>();
+
+    let mut partitions: State<Partitions> = hooks.use_state_default();
+    let mpd = ctx.mpd.clone();
+    hooks.use_future(async move {
+        loop {
+            if mpd.capabilities().supports(Feature::Partitions) {
+                if let Ok(mut client) = mpd.bind().await {
+                    let result = async {
+                        Ok(Partitions {
+                            names: client.list_partitions().await?,
+                            current: client.status().await?.partition,
+                            outputs: client.outputs().await?,
+                        })
+                    }
+                    .await;
+                    match result {
+                        Ok(result) => partitions.set(result),
+                        Err(error) => mpd.handle_error(&error),
+                    }
+                }
+            }
+
+            mpd.wait_for(&[Subsystem::Partition, Subsystem::Output]).await;
+        }
+    });
+
+    let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
+    let switch = hooks.use_async_handler(move |name: String| {
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
+        async move {
+            if let Some(reason) = mpd.capabilities().unavailable_reason("partition") {
+                toasts.error(format!("partition: {reason}"));
+                return;
+            }
+            if let Err(error) = mpd.switch_partition(&name).await {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
+            }
+        }
+    });
+    let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
+    let action = hooks.use_async_handler(move |action: Action| {
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
+        async move {
+            if let Some(reason) = mpd.capabilities().unavailable_reason(action.command()) {
+                toasts.error(format!("{}: {reason}", action.command()));
+                return;
+            }
+            let Ok(mut client) = mpd.bind_then_notify().await else {
+                return;
+            };
+            let result = match &action {
+                Action::Create(name) => client.new_partition(name).await,
+                Action::Delete(name) => client.delete_partition(name).await,
+                Action::MoveOutput(name) => client.move_output(name).await,
+            };
+            if let Err(error) = result {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
+            }
+        }
+    });
+
+    let mut pane = hooks.use_state_default::<Pane>();
+    let mut partition_cursor = hooks.use_state(|| 0usize);
+    let mut output_cursor = hooks.use_state(|| 0usize);
+    // Name of a new partition, while it's being typed.
+    let mut new_name: State<Option<String>> = hooks.use_state_default();
+    let mut text_input = ctx.text_input;
+    hooks.use_terminal_events(move |event| match event {
+        TerminalEvent::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) => {
+            if new_name.read().is_some() {
+                match code {
+                    KeyCode::Enter => {
+                        let name = new_name.write().take().unwrap_or_default();
+                        text_input.set(false);
+                        if !name.trim().is_empty() {
+                            (action)(Action::Create(name.trim().to_owned()));
+                        }
+                    },
+                    KeyCode::Esc => {
+                        new_name.set(None);
+                        text_input.set(false);
+                    },
+                    _ => {},
+                }
+                return;
+            }
+
+            let (cursor, len) = match pane.get() {
+                Pane::Partitions => (&mut partition_cursor, partitions.read().names.len()),
+                Pane::Outputs => (&mut output_cursor, partitions.read().outputs.len()),
+            };
+            match code {
+                KeyCode::Tab => {
+                    pane.set(match pane.get() {
+                        Pane::Partitions => Pane::Outputs,
+                        Pane::Outputs => Pane::Partitions,
+                    });
+                },
+                KeyCode::Up | KeyCode::Char('k') => cursor.set(cursor.get().saturating_sub(1)),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    cursor.set((cursor.get() + 1).min(len.saturating_sub(1)));
+                },
+                KeyCode::Char('n') => {
+                    new_name.set(Some(String::new()));
+                    text_input.set(true);
+                },
+                KeyCode::Enter => match pane.get() {
+                    Pane::Partitions => {
+                        if let Some(name) = partitions.read().names.get(partition_cursor.get()) {
+                            (switch)(name.clone());
+                        }
+                    },
+                    Pane::Outputs => {
+                        if let Some(output) = partitions.read().outputs.get(output_cursor.get()) {
+                            (action)(Action::MoveOutput(output.name.clone()));
+                        }
+                    },
+                },
+                KeyCode::Char('D') if pane.get() == Pane::Partitions => {
+                    if let Some(name) = partitions.read().names.get(partition_cursor.get()) {
+                        (action)(Action::Delete(name.clone()));
+                    }
+                },
+                _ => {},
+            }
+        },
+        _ => {},
+    });
+
+    if !ctx.mpd.capabilities().supports(Feature::Partitions) {
+        return element! {
+            View(
+                width: Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+            ) {
+                Text(content: "Partitions are not supported by the server")
+            }
+        };
+    }
+
+    let item_color = |is_focused: bool, is_current: bool| match (is_focused, is_current) {
+        (true, _) => Color::White,
+        (false, true) => Color::Blue,
+        (false, false) => Color::Grey,
+    };
+    let render_partition = move |index: usize| {
+        let partitions = partitions.read();
+        let name = partitions.names.get(index)?;
+        let is_current = partitions.current.as_ref() == Some(name);
+        let is_focused = pane.get() == Pane::Partitions && index == partition_cursor.get();
+        let row = element! {
+            Text(
+                color: item_color(is_focused, is_current),
+                content: format!("{} {name}", if is_current { "●" } else { " " }),
+            )
+        };
+        Some(row.into_any())
+    };
+    let render_output = move |index: usize| {
+        let partitions = partitions.read();
+        let output = partitions.outputs.get(index)?;
+        let is_focused = pane.get() == Pane::Outputs && index == output_cursor.get();
+        let row = element! {
+            Text(
+                color: item_color(is_focused, output.enabled),
+                content: format!(
+                    "{} {} ({})",
+                    if output.enabled { "●" } else { "○" },
+                    output.name,
+                    output.plugin,
+                ),
+            )
+        };
+        Some(row.into_any())
+    };
+
+    let partitions = partitions.read();
+    element! {
+        View(
+            width: Percent(100.0),
+            height: Percent(100.0),
+            flex_direction: FlexDirection::Row,
+            gap: 4,
+        ) {
+            View(flex_direction: FlexDirection::Column) {
+                Text(
+                    weight: Weight::Bold,
+                    decoration: if pane.get() == Pane::Partitions {
+                        TextDecoration::Underline
+                    } else {
+                        TextDecoration::None
+                    },
+                    content: "Partitions",
+                )
+                View(flex_grow: 1.0) {
+                    VirtualList(
+                        len: partitions.names.len(),
+                        cursor: partition_cursor.get(),
+                        render_row: render_partition,
+                    )
+                }
+                #(new_name.read().is_some().then(|| element! {
+                    View(flex_direction: FlexDirection::Row, gap: 1) {
+                        Text(content: "+")
+                        TextInput(
+                            has_focus: true,
+                            value: new_name.read().clone().unwrap_or_default(),
+                            on_change: move |value| new_name.set(Some(value)),
+                        )
+                    }
+                }))
+            }
+            View(flex_direction: FlexDirection::Column) {
+                Text(
+                    weight: Weight::Bold,
+                    decoration: if pane.get() == Pane::Outputs {
+                        TextDecoration::Underline
+                    } else {
+                        TextDecoration::None
+                    },
+                    content: "Outputs",
+                )
+                View(flex_grow: 1.0) {
+                    VirtualList(
+                        len: partitions.outputs.len(),
+                        cursor: output_cursor.get(),
+                        render_row: render_output,
+                    )
+                }
+            }
+            View(flex_direction: FlexDirection::Column) {
+                Text(weight: Weight::Light, content: "Tab: switch list")
+                Text(weight: Weight::Light, content: "Enter: switch partition / move output here")
+                Text(weight: Weight::Light, content: "n: new partition, D: delete partition")
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@ use iocraft::prelude::*;
 use crate::{
     app::AppContext,
     components::{Duration, ProgressBar},
+    mpd::{self, Subsystem},
 };
 
 /// State for [`CurrentSongScreen`].
@@ -25,21 +26,43 @@ enum Action {
     Stop,
 }
 
+impl Action {
+    /// MPD command sent by the action when the player is in the `state`, to check if
+    /// it's allowed.
+    fn command(&self, state: mpd::State) -> &'static str {
+        match self {
+            Action::Rewind(_) => "seekcur",
+            Action::Next => "next",
+            Action::Prev => "previous",
+            Action::Toggle if state == mpd::State::Stop => "play",
+            Action::Toggle => "pause",
+            Action::Stop => "stop",
+        }
+    }
+}
+
 /// Current MPD status screen.
 #[component]
 pub fn PlaybackScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
     let ctx = hooks.use_context::<AppContext>();
 
     let mut current: State<Option<CurrentSong>> = hooks.use_state(|| None);
-    let mut mpd = ctx.mpd.clone();
+    let mpd = ctx.mpd.clone();
     hooks.use_future(async move {
         loop {
-            {
-                let mut client = mpd.bind().await;
-                let song = client.currentsong().unwrap();
-                let status = client.status().unwrap();
+            let result = match mpd.bind().await {
+                Ok(mut client) => match client.current_song().await {
+                    Ok(song) => client.status().await.map(|status| (song, status)),
+                    Err(error) => Err(error),
+                },
+                Err(_) => {
+                    mpd.wait_for(&[Subsystem::Player, Subsystem::Playlist]).await;
+                    continue;
+                },
+            };
 
-                if let Some(song) = song {
+            match result {
+                Ok((Some(song), status)) => {
                     let (current_elapsed, current_duration) = current
                         .read()
                         .as_ref()
@@ -60,53 +83,90 @@ pub fn PlaybackScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
                             .unwrap_or_default(),
                         state: status.state,
                     }));
-                } else {
-                    current.set(None);
-                }
+                },
+                Ok((None, _)) => current.set(None),
+                Err(error) => mpd.handle_error(&error),
             }
 
-            mpd.wait_for_update().await;
+            mpd.wait_for(&[Subsystem::Player, Subsystem::Playlist]).await;
         }
     });
 
-    let mpd = ctx.mpd.clone();
+    // MPD doesn't report elapsed time changes via `idle`, so it is ticked locally.
     hooks.use_future(async move {
         loop {
-            smol::Timer::interval(std::time::Duration::from_millis(500)).await;
-            mpd.notify_update().await;
+            smol::Timer::after(std::time::Duration::from_secs(1)).await;
+            if let Some(song) = current.write().as_mut() {
+                if matches!(song.state, mpd::State::Play) {
+                    song.elapsed = (song.elapsed + chrono::Duration::seconds(1)).min(song.duration);
+                }
+            }
         }
     });
 
     let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
     let change_postion_to = hooks.use_async_handler(move |amount: f32| {
-        let mut mpd = mpd.clone();
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
         let duration = current.read().as_ref().map(|current| current.duration).unwrap_or_default();
         async move {
-            let mut client = mpd.bind_then_notify().await;
-            client.rewind((duration.as_seconds_f32() * amount) as f64).unwrap();
+            if let Some(reason) = mpd.capabilities().unavailable_reason("seekcur") {
+                toasts.error(format!("seekcur: {reason}"));
+                return;
+            }
+            let Ok(mut client) = mpd.bind_then_notify().await else {
+                return;
+            };
+            let result = client.seek((duration.as_seconds_f32() * amount) as f64).await;
+            if let Err(error) = result {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
+            }
         }
     });
     let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
     let action = hooks.use_async_handler(move |action: Action| {
-        let mut mpd = mpd.clone();
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
         async move {
-            let mut client = mpd.bind_then_notify().await;
-            match action {
+            let Ok(mut client) = mpd.bind_then_notify().await else {
+                return;
+            };
+            // Toggle starts playback when the player is stopped, so its command is known
+            // only from the status.
+            let state = match action {
+                Action::Toggle => match client.status().await {
+                    Ok(status) => status.state,
+                    Err(error) => {
+                        mpd.handle_error(&error);
+                        toasts.mpd_error(&error);
+                        return;
+                    },
+                },
+                _ => mpd::State::default(),
+            };
+            let command = action.command(state);
+            if let Some(reason) = mpd.capabilities().unavailable_reason(command) {
+                toasts.error(format!("{command}: {reason}"));
+                return;
+            }
+            let result = match action {
                 Action::Rewind(amount) => {
                     let elapsed =
                         current.read().as_ref().map(|current| current.elapsed).unwrap_or_default();
-                    client.rewind((elapsed.as_seconds_f32() + amount) as f64).unwrap();
+                    client.seek((elapsed.as_seconds_f32() + amount) as f64).await
                 },
-                Action::Next => client.next().unwrap(),
-                Action::Prev => client.prev().unwrap(),
-                Action::Toggle => {
-                    if matches!(client.status(), Ok(mpd::Status { state: mpd::State::Stop, .. })) {
-                        client.play().unwrap()
-                    } else {
-                        client.toggle_pause().unwrap()
-                    }
-                },
-                Action::Stop => client.stop().unwrap(),
+                Action::Next => client.next().await,
+                Action::Prev => client.prev().await,
+                Action::Toggle if state == mpd::State::Stop => client.play().await,
+                Action::Toggle => client.toggle_pause().await,
+                Action::Stop => client.stop().await,
+            };
+            if let Err(error) = result {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
             }
         }
     });
@@ -124,49 +184,86 @@ pub fn PlaybackScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
         _ => {},
     });
 
+    let capabilities = ctx.mpd.capabilities();
+    let state = current.read().as_ref().map(|song| song.state).unwrap_or_default();
+    let color_for = |action: Action| {
+        capabilities.unavailable_reason(action.command(state)).map(|_| Color::DarkGrey)
+    };
+    let hints = [
+        ("p", "play/pause", Action::Toggle),
+        ("P", "stop", Action::Stop),
+        ("<", "previous", Action::Prev),
+        (">", "next", Action::Next),
+        ("←/→", "seek", Action::Rewind(0.0)),
+    ];
+
     element! {
         View(
             width: Percent(100.0),
             height: Percent(100.0),
             flex_direction: FlexDirection::Column,
             align_items: AlignItems::Center,
-            justify_content: JustifyContent::Center,
-            flex_direction: FlexDirection::Column,
         ) {
-            #(match &*current.read() {
-                Some(song) => element!{
-                    View(
-                        width: Percent(60.0),
-                        height: Percent(100.0),
-                        flex_direction: FlexDirection::Column,
-                        align_items: AlignItems::Center,
-                        justify_content: JustifyContent::Center,
-                        flex_direction: FlexDirection::Column,
-                    ) {
-                        Text(color: Color::Blue, weight: Weight::Bold, content: &song.artist)
-                        Text(color: Color::DarkBlue, decoration: TextDecoration::Underline, content: &song.title)
-                        Text()
-                        ProgressBar(
-                            amount: song.elapsed.as_seconds_f32() / song.duration.as_seconds_f32(),
-                            handler: change_postion_to,
-                        )
-                        View(width: Percent(100.0), justify_content: JustifyContent::SpaceBetween) {
-                            View(gap: 1) {
-                                Text(weight: Weight::Light, content: match song.state {
-                                    mpd::State::Pause => "️️⏸ ",
-                                    mpd::State::Play => "▶",
-                                    mpd::State::Stop => "⏹"
-                                })
-                                Duration(weight: Weight::Light, duration: song.elapsed)
+            View(
+                width: Percent(100.0),
+                flex_grow: 1.0,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+            ) {
+                #(match &*current.read() {
+                    Some(song) => element!{
+                        View(
+                            width: Percent(60.0),
+                            height: Percent(100.0),
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::Center,
+                        ) {
+                            Text(color: Color::Blue, weight: Weight::Bold, content: &song.artist)
+                            Text(
+                                color: Color::DarkBlue,
+                                decoration: TextDecoration::Underline,
+                                content: &song.title,
+                            )
+                            Text()
+                            ProgressBar(
+                                amount: song.elapsed.as_seconds_f32()
+                                    / song.duration.as_seconds_f32(),
+                                handler: change_postion_to,
+                                color: color_for(Action::Rewind(0.0)),
+                            )
+                            View(
+                                width: Percent(100.0),
+                                justify_content: JustifyContent::SpaceBetween,
+                            ) {
+                                View(gap: 1) {
+                                    Text(weight: Weight::Light, content: match song.state {
+                                        mpd::State::Pause => "️️⏸ ",
+                                        mpd::State::Play => "▶",
+                                        mpd::State::Stop => "⏹"
+                                    })
+                                    Duration(weight: Weight::Light, duration: song.elapsed)
+                                }
+                                Duration(weight: Weight::Light, duration: song.duration)
                             }
-                            Duration(weight: Weight::Light, duration: song.duration)
                         }
-                    }
-                }.into_any(),
-                None => element!{
-                    Text(content: "Nothing is playing...")
-                }.into_any(),
-            })
+                    }.into_any(),
+                    None => element!{
+                        Text(content: "Nothing is playing...")
+                    }.into_any(),
+                })
+            }
+            // Keys of actions which aren't allowed are greyed out, like in the status bar.
+            View(gap: 2) {
+                #(hints.into_iter().map(|(key, label, action)| element! {
+                    Text(
+                        weight: Weight::Light,
+                        color: color_for(action),
+                        content: format!("{key}: {label}"),
+                    )
+                }))
+            }
         }
     }
 }
@@ -0,0 +1,231 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use eyre::WrapErr;
+use iocraft::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    app::AppContext,
+    mpd::{MpdAddress, Timeouts, DEFAULT_PORT},
+    queue::QueueConfig,
+};
+
+/// Name of the profile made from command line arguments, when there are no others.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Named MPD server settings.
+///
+/// Each connection setting falls back to the `MPD_HOST`/`MPD_PORT` environment variables,
+/// and then to the default `localhost:6600` without a password.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: String,
+    /// MPD host: hostname, path to a unix socket, or `@name` of an abstract socket.
+    /// Can be prefixed with `password@`, like `MPD_HOST`.
+    pub host: Option<String>,
+    /// MPD port, used only for TCP connections.
+    pub port: Option<u16>,
+    /// MPD password, overrides the one from `host`.
+    pub password: Option<String>,
+    /// Partition to switch to after connecting.
+    pub partition: Option<String>,
+    #[serde(default)]
+    pub timeouts: ProfileTimeouts,
+}
+
+impl Profile {
+    /// Resolve the MPD address and password from the profile and the environment.
+    pub fn mpd_address(&self) -> eyre::Result<(MpdAddress, Option<String>)> {
+        let port = match (self.port, std::env::var("MPD_PORT")) {
+            (Some(port), _) => port,
+            (None, Ok(port)) => port
+                .parse()
+                .map_err(|error| eyre::eyre!("Invalid MPD_PORT \"{port}\": {error}"))?,
+            (None, Err(_)) => DEFAULT_PORT,
+        };
+
+        let (address, password) = match self.host.clone().or_else(|| std::env::var("MPD_HOST").ok())
+        {
+            Some(host) => MpdAddress::from_host(&host, port),
+            None => (MpdAddress::Tcp { host: "localhost".to_owned(), port }, None),
+        };
+
+        Ok((address, self.password.clone().or(password)))
+    }
+
+    /// Resolve timeouts of the profile, with defaults for the unset ones.
+    pub fn timeouts(&self) -> eyre::Result<Timeouts> {
+        let seconds = |name: &str, seconds: f64| {
+            Duration::try_from_secs_f64(seconds)
+                .wrap_err_with(|| format!("Invalid timeout {name} = {seconds}"))
+        };
+
+        let mut timeouts = Timeouts::default();
+        if let Some(read) = self.timeouts.read {
+            timeouts.read = seconds("read", read)?;
+        }
+        if let Some(write) = self.timeouts.write {
+            timeouts.write = seconds("write", write)?;
+        }
+        if let Some(keepalive) = self.timeouts.keepalive {
+            let keepalive = seconds("keepalive", keepalive)?;
+            timeouts.keepalive = (!keepalive.is_zero()).then_some(keepalive);
+        }
+        for (command, timeout) in &self.timeouts.commands {
+            timeouts.commands.insert(command.clone(), seconds(command, *timeout)?);
+        }
+
+        Ok(timeouts)
+    }
+}
+
+/// Timeouts of a [`Profile`] in seconds, see [`Timeouts`] for their defaults.
+/// Zero `keepalive` disables pings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileTimeouts {
+    pub read: Option<f64>,
+    pub write: Option<f64>,
+    pub keepalive: Option<f64>,
+    /// Read timeouts of specific commands by name.
+    #[serde(default)]
+    pub commands: BTreeMap<String, f64>,
+}
+
+/// Config file with server profiles, in TOML:
+///
+/// ```toml
+/// default_profile = "desk"
+///
+/// [profiles.desk]
+/// host = "localhost"
+///
+/// [profiles.living-room]
+/// host = "secret@192.168.1.10"
+/// partition = "living-room"
+///
+/// [profiles.living-room.timeouts]
+/// read = 30
+/// commands = { listall = 120 }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Profile to start with, unless `--profile` is given.
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub queue: QueueConfig,
+}
+
+impl ConfigFile {
+    /// `$XDG_CONFIG_HOME/minisong/config.toml`, or `~/.config/minisong/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("minisong").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Reading config file {}", path.display()))?;
+        toml::from_str(&content).wrap_err_with(|| format!("Parsing config file {}", path.display()))
+    }
+
+    /// Every profile from the file, sorted by name.
+    pub fn into_profiles(self) -> Vec<Profile> {
+        self.profiles.into_iter().map(|(name, profile)| Profile { name, ..profile }).collect()
+    }
+}
+
+/// Server profiles and the one currently in use, set in [`crate::app::Minisong`].
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    pub list: Arc<[Profile]>,
+    pub current: State<usize>,
+    /// Incremented on every switch, so the session restarts even for the same profile.
+    pub session: State<u32>,
+}
+
+impl Profiles {
+    pub fn current(&self) -> &Profile {
+        &self.list[self.current.get()]
+    }
+
+    /// Start a new session with the profile at `index`.
+    pub fn switch(&self, index: usize) {
+        let (mut current, mut session) = (self.current, self.session);
+        current.set(index);
+        session.set(session.get() + 1);
+    }
+}
+
+/// Switcher for server profiles. Works without [`AppContext`] too, so another profile
+/// can be picked when connecting has failed.
+#[component]
+pub fn ProfilesScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let profiles = hooks.use_context::<Profiles>().clone();
+    let mpd = hooks.try_use_context::<AppContext>().map(|ctx| ctx.mpd.clone());
+
+    let mut cursor = hooks.use_state(|| profiles.current.get());
+    hooks.use_terminal_events({
+        let profiles = profiles.clone();
+        move |event| match event {
+            TerminalEvent::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) => match code {
+                KeyCode::Up | KeyCode::Char('k') => cursor.set(cursor.get().saturating_sub(1)),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    cursor.set((cursor.get() + 1).min(profiles.list.len() - 1));
+                },
+                KeyCode::Enter => {
+                    if let Some(mpd) = &mpd {
+                        mpd.close();
+                    }
+                    profiles.switch(cursor.get());
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+    });
+
+    element! {
+        View(flex_direction: FlexDirection::Column) {
+            Text(weight: Weight::Bold, content: "Profiles")
+            #(profiles.list.iter().enumerate().map(|(index, profile)| {
+                let is_current = index == profiles.current.get();
+                // Host may contain a password, so only the resolved address is shown.
+                let address = match profile.mpd_address() {
+                    Ok((address, _)) => address.to_string(),
+                    Err(error) => error.to_string(),
+                };
+                let color = match (index == cursor.get(), is_current) {
+                    (true, _) => Color::White,
+                    (false, true) => Color::Blue,
+                    (false, false) => Color::Grey,
+                };
+                element! {
+                    Text(
+                        color,
+                        content: format!(
+                            "{} {} ({})",
+                            if is_current { "●" } else { " " },
+                            profile.name,
+                            address,
+                        ),
+                    )
+                }
+            }))
+            Text()
+            Text(weight: Weight::Light, content: "Enter: connect to the profile")
+        }
+    }
+}
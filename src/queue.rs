@@ -0,0 +1,1298 @@
+use std::{collections::HashSet, ops::Range};
+
+use iocraft::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    app::AppContext,
+    components::VirtualList,
+    mpd::{Command, CommandList, Connection, MpdResult, QueuePlace, Song, Subsystem},
+};
+
+mod columns;
+mod filter;
+mod history;
+mod selection;
+
+pub use columns::{Column, Source, Width};
+use filter::Filter;
+use history::{History, Replay, Replayed};
+use selection::Selection;
+
+/// How many rows a single mouse wheel step scrolls.
+const WHEEL_STEP: usize = 3;
+
+/// How much a single key press raises or lowers the priority.
+const PRIORITY_STEP: u8 = 10;
+
+/// Songs are loaded by windows of this many positions, as they're shown.
+const WINDOW: usize = 256;
+
+/// Songs in the queue for [`QueueScreen`]. Ids of all of them are kept in sync with MPD by
+/// fetching only changes since the last known version of the queue, while songs themselves
+/// are loaded lazily, by windows in view.
+#[derive(Debug, Default, Clone)]
+struct Queue {
+    /// Ids of songs in the queue order.
+    ids: Vec<u32>,
+    /// Songs in the queue order, `None` until they're loaded.
+    songs: Vec<Option<Song>>,
+    /// Version of the queue `ids` are from, or `None` if they have to be fetched again.
+    version: Option<u32>,
+    /// Id of the current song, if there is any.
+    current: Option<u32>,
+}
+
+impl Queue {
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Place of the song at `index`, with the priority 0 if the song isn't loaded yet.
+    fn place(&self, index: usize) -> Option<QueuePlace> {
+        let id = *self.ids.get(index)?;
+        let prio = self.songs[index].as_ref().and_then(|song| song.place).map(|place| place.prio);
+        Some(QueuePlace { id, pos: index as u32, prio: prio.unwrap_or_default() })
+    }
+
+    /// Windows of positions with songs in the `range` which aren't loaded yet.
+    fn missing(&self, range: Range<usize>) -> Vec<Range<u32>> {
+        let end = range.end.min(self.len());
+        let start = range.start.min(end) / WINDOW * WINDOW;
+        (start..end)
+            .step_by(WINDOW)
+            .map(|start| start..(start + WINDOW).min(self.len()))
+            .filter(|window| self.songs[window.clone()].iter().any(Option::is_none))
+            .map(|window| window.start as u32..window.end as u32)
+            .collect()
+    }
+
+    /// Apply `changes` fetched by [`fetch_changes`]. Returns `false` without changing
+    /// anything if they don't add up, and then the whole queue has to be fetched.
+    fn apply(&mut self, changes: Changes) -> bool {
+        let Changes { version, len, changed } = changes;
+        let len = len as usize;
+        let changed: Vec<(usize, u32)> = changed
+            .into_iter()
+            .map(|(pos, id)| (pos as usize, id))
+            .filter(|&(pos, _)| pos < len)
+            .collect();
+        // Every song past the old end is new, so it has to be among the changed ones.
+        let end = self.len().min(len);
+        if changed.iter().filter(|&&(pos, _)| pos >= end).count() != len - end {
+            return false;
+        }
+
+        // Priority change together with a move looks just like the move, so every changed
+        // song is dropped, and only windows of them in view are loaded again.
+        self.ids.truncate(len);
+        self.ids.resize(len, 0);
+        self.songs.truncate(len);
+        self.songs.resize(len, None);
+        for (pos, id) in changed {
+            self.ids[pos] = id;
+            self.songs[pos] = None;
+        }
+        self.version = Some(version);
+        true
+    }
+
+    /// Put `songs` loaded at the queue `version` to their places. Returns `false` without
+    /// changing anything if the queue has changed since, and then they have to be loaded
+    /// again.
+    fn fill(&mut self, version: Option<u32>, songs: Vec<Song>) -> bool {
+        if version != self.version {
+            return false;
+        }
+        for song in songs {
+            let Some(place) = song.place else {
+                continue;
+            };
+            // Songs may be newer than the version, and then they're loaded again after
+            // the next sync.
+            if self.ids.get(place.pos as usize) == Some(&place.id) {
+                self.songs[place.pos as usize] = Some(song);
+            }
+        }
+        true
+    }
+}
+
+/// Changes of the queue since some version of it, see [`fetch_changes`].
+#[derive(Debug)]
+struct Changes {
+    /// Version of the queue after the changes.
+    version: u32,
+    len: u32,
+    /// Positions and ids of songs which have changed, in the queue order.
+    changed: Vec<(u32, u32)>,
+}
+
+/// Fetch positions and ids of songs changed since the queue `version`, or of every song
+/// if it's `None`.
+async fn fetch_changes(client: &mut Connection, version: Option<u32>) -> MpdResult<Changes> {
+    // Every song has changed since the version 0.
+    let (status, changed) = client.status_and_queue_changes(version.unwrap_or(0)).await?;
+    Ok(Changes { version: status.queue_version, len: status.queue_len, changed })
+}
+
+/// Actions for [`QueueScreen`].
+#[derive(Debug, Clone)]
+enum Action {
+    Play(u32),
+    /// Commands for every selected song, sent at once and recorded to undo.
+    Batch(CommandList),
+    /// Add songs with the ids to the stored playlist, whether they're loaded or not.
+    AddToPlaylist { name: String, ids: Vec<u32> },
+    Undo,
+    Redo,
+}
+
+impl Action {
+    /// MPD commands sent by the action, to check if they're allowed. Undo and redo send
+    /// commands of the edit they replay, so they're checked with it.
+    fn commands(&self) -> Vec<&'static str> {
+        match self {
+            Action::Play(_) => vec!["playid"],
+            Action::Batch(list) => list.commands().iter().map(Command::name).collect(),
+            Action::AddToPlaylist { .. } => vec!["playlistid", "playlistadd"],
+            Action::Undo | Action::Redo => Vec::new(),
+        }
+    }
+}
+
+/// Text typed in the footer of [`QueueScreen`].
+#[derive(Debug, Clone)]
+enum Input {
+    /// Name of the playlist to add the selection to.
+    Playlist(String),
+    /// Priority to set for the selection.
+    Priority(String),
+}
+
+impl Input {
+    fn text(&self) -> &str {
+        match self {
+            Input::Playlist(text) | Input::Priority(text) => text,
+        }
+    }
+
+    fn with_text(&self, text: String) -> Self {
+        match self {
+            Input::Playlist(_) => Input::Playlist(text),
+            Input::Priority(_) => Input::Priority(text),
+        }
+    }
+
+    fn prompt(&self) -> &'static str {
+        match self {
+            Input::Playlist(_) => "Add to playlist:",
+            Input::Priority(_) => "Priority (0-255):",
+        }
+    }
+}
+
+/// Songs dragged with the mouse, from the row where dragging started to the one under the
+/// pointer.
+#[derive(Debug, Clone, Copy)]
+struct Drag {
+    from: usize,
+    over: usize,
+}
+
+/// Queue settings from the `[queue]` table of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueConfig {
+    /// Columns of the queue table, see [`Column`].
+    #[serde(default = "Column::defaults")]
+    pub columns: Vec<Column>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self { columns: Column::defaults() }
+    }
+}
+
+/// Songs in the queue as a table, with the current song highlighted.
+#[component]
+pub fn QueueScreen(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let ctx = hooks.use_context::<AppContext>();
+    let config = ctx.queue_config.clone();
+
+    let mut queue: State<Queue> = hooks.use_state_default();
+    // Fuzzy filter of the table, while it's open.
+    let mut filter: State<Option<Filter>> = hooks.use_state_default();
+    // Windows of the queue being loaded, by their first positions.
+    let mut loading: State<HashSet<u32>> = hooks.use_state_default();
+    let mut selection: State<Selection> = hooks.use_state_default();
+    // Rows of the table in view, reported by the list.
+    let mut view = hooks.use_state(|| (0usize, 0usize));
+    let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
+    let load = hooks.use_async_handler(move |windows: Vec<Range<u32>>| {
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
+        let windows: Vec<Range<u32>> =
+            windows.into_iter().filter(|window| !loading.read().contains(&window.start)).collect();
+        if !windows.is_empty() {
+            loading.write().extend(windows.iter().map(|window| window.start));
+        }
+        let version = queue.read().version;
+        async move {
+            if windows.is_empty() {
+                return;
+            }
+            // Failed windows are loaded again once the queue is synced, after it changes
+            // or the connection is restored.
+            let result = match mpd.bind_bulk().await {
+                Ok(mut client) => client.playlist_info_ranges(&windows).await.map(Some),
+                Err(_) => Ok(None),
+            };
+            match result {
+                Ok(Some(songs)) => {
+                    let mut queue = queue.write();
+                    if queue.fill(version, songs) {
+                        if let Some(filter) = &mut *filter.write() {
+                            filter.refresh(&queue.songs);
+                        }
+                    }
+                },
+                Ok(None) => {},
+                Err(error) => {
+                    mpd.handle_error(&error);
+                    toasts.mpd_error(&error);
+                },
+            }
+            let mut loading = loading.write();
+            for window in &windows {
+                loading.remove(&window.start);
+            }
+        }
+    });
+
+    let mpd = ctx.mpd.clone();
+    let load_all = load.clone();
+    hooks.use_future(async move {
+        loop {
+            if let Ok(mut client) = mpd.bind_bulk().await {
+                let version = queue.read().version;
+                match fetch_changes(&mut client, version).await {
+                    Ok(changes) => {
+                        let missing = {
+                            let mut queue = queue.write();
+                            if !queue.apply(changes) {
+                                queue.version = None;
+                                continue;
+                            }
+                            loading.write().clear();
+                            selection.write().retain(&queue.ids);
+                            // Filter matches every song, so all of them are loaded, and
+                            // otherwise changed songs in view are loaded again right away.
+                            match &mut *filter.write() {
+                                Some(filter) => {
+                                    filter.refresh(&queue.songs);
+                                    queue.missing(0..queue.len())
+                                },
+                                None => {
+                                    let (start, end) = view.get();
+                                    queue.missing(start..end)
+                                },
+                            }
+                        };
+                        (load_all)(missing);
+                    },
+                    Err(error) => {
+                        queue.write().version = None;
+                        mpd.handle_error(&error);
+                    },
+                }
+            }
+
+            mpd.wait_for(&[Subsystem::Playlist]).await;
+        }
+    });
+
+    let mpd = ctx.mpd.clone();
+    hooks.use_future(async move {
+        loop {
+            if let Ok(mut client) = mpd.bind().await {
+                match client.status().await {
+                    Ok(status) => queue.write().current = status.song.map(|song| song.id),
+                    Err(error) => mpd.handle_error(&error),
+                }
+            }
+
+            mpd.wait_for(&[Subsystem::Player, Subsystem::Playlist]).await;
+        }
+    });
+
+    let mut history: State<History> = hooks.use_state_default();
+    let mpd = ctx.mpd.clone();
+    let toasts = ctx.toasts.clone();
+    let action = hooks.use_async_handler(move |action: Action| {
+        let mpd = mpd.clone();
+        let toasts = toasts.clone();
+        async move {
+            let taken = match action {
+                Action::Undo => history.write().take_undo(),
+                Action::Redo => history.write().take_redo(),
+                _ => None,
+            };
+            let commands = taken.as_ref().map_or_else(|| action.commands(), Replay::commands);
+            let capabilities = mpd.capabilities();
+            let refused = commands.into_iter().find_map(|command| {
+                Some((command, capabilities.unavailable_reason(command)?))
+            });
+            if let Some((command, reason)) = refused {
+                if let Some(taken) = taken {
+                    history.write().put_back(taken);
+                }
+                toasts.error(format!("{command}: {reason}"));
+                return;
+            }
+            let Ok(mut client) = mpd.bind_then_notify().await else {
+                if let Some(taken) = taken {
+                    history.write().put_back(taken);
+                }
+                return;
+            };
+            let result = match action {
+                Action::Play(id) => client.play_id(id).await,
+                // History is updated while the client is bound, so edits are recorded in
+                // the order they're made.
+                Action::Batch(list) => history::run(&mut client, &list)
+                    .await
+                    .map(|recorded| history.write().push(recorded)),
+                Action::AddToPlaylist { name, ids } => match client.playlist_ids(&ids).await {
+                    Ok(songs) => {
+                        let list = songs.iter().fold(CommandList::new(), |list, song| {
+                            list.playlist_add(&name, &song.file)
+                        });
+                        client.batch(&list).await.map(drop)
+                    },
+                    Err(error) => Err(error),
+                },
+                Action::Undo | Action::Redo => {
+                    let Some(taken) = taken else {
+                        return;
+                    };
+                    match history::replay(&mut client, &taken).await {
+                        Ok(Replayed::Done(version)) => {
+                            history.write().finish(taken, version);
+                            Ok(())
+                        },
+                        Ok(Replayed::Refused) => {
+                            history.write().clear();
+                            toasts.error("Queue was changed by another client, history is cleared");
+                            Ok(())
+                        },
+                        Ok(Replayed::Raced) => {
+                            history.write().clear();
+                            toasts.error("Queue was changed by another client while replaying");
+                            Ok(())
+                        },
+                        // Failed replay can be tried again, and it's refused if it's made
+                        // in part.
+                        Err(error) => {
+                            history.write().put_back(taken);
+                            Err(error)
+                        },
+                    }
+                },
+            };
+            if let Err(error) = result {
+                mpd.handle_error(&error);
+                toasts.mpd_error(&error);
+            }
+        }
+    });
+
+    let mut cursor = hooks.use_state(|| 0usize);
+    let mut input: State<Option<Input>> = hooks.use_state_default();
+    let mut text_input = ctx.text_input;
+    let mut show_priority = hooks.use_state(|| {
+        config.columns.iter().any(|column| column.source == Source::Priority)
+    });
+    let toasts = ctx.toasts.clone();
+    let load_all = load.clone();
+    let drag_action = action.clone();
+    hooks.use_terminal_events(move |event| match event {
+        TerminalEvent::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) => {
+            let queue = queue.read();
+            let last = queue.len().saturating_sub(1);
+            let at = cursor.get().min(last);
+            let (start, end) = view.get();
+            let page = (end - start).max(1);
+
+            if filter.read().is_some() {
+                match code {
+                    KeyCode::Enter => {
+                        let top = filter.write().take().and_then(|filter| {
+                            filter.matches().first().copied()
+                        });
+                        text_input.set(false);
+                        if let Some((index, &id)) =
+                            top.and_then(|index| Some((index, queue.ids.get(index)?)))
+                        {
+                            (action)(Action::Play(id));
+                            cursor.set(index);
+                        }
+                    },
+                    KeyCode::Esc => {
+                        filter.set(None);
+                        text_input.set(false);
+                    },
+                    _ => {},
+                }
+                return;
+            }
+
+            if input.read().is_some() {
+                match code {
+                    KeyCode::Enter => {
+                        let typed = input.write().take();
+                        text_input.set(false);
+                        let ids: Vec<u32> = selection
+                            .read()
+                            .targets(&queue.ids, at)
+                            .into_iter()
+                            .map(|index| queue.ids[index])
+                            .collect();
+                        let request = match typed {
+                            Some(Input::Playlist(name)) if !name.trim().is_empty() => {
+                                Action::AddToPlaylist { name: name.trim().to_owned(), ids }
+                            },
+                            Some(Input::Priority(prio)) => match prio.trim().parse::<u8>() {
+                                Ok(prio) => Action::Batch(
+                                    ids.into_iter().fold(CommandList::new(), |list, id| {
+                                        list.prio_id(prio, id)
+                                    }),
+                                ),
+                                Err(_) => {
+                                    toasts.error("Priority is a number from 0 to 255");
+                                    return;
+                                },
+                            },
+                            _ => return,
+                        };
+                        (action)(request);
+                        selection.write().clear();
+                    },
+                    KeyCode::Esc => {
+                        input.set(None);
+                        text_input.set(false);
+                    },
+                    _ => {},
+                }
+                return;
+            }
+
+            // Selected songs, or the one under the cursor.
+            let targets: Vec<QueuePlace> = selection
+                .read()
+                .targets(&queue.ids, at)
+                .into_iter()
+                .filter_map(|index| queue.place(index))
+                .collect();
+            // Songs gather before the destination, and the cursor follows them.
+            let destination = match code {
+                KeyCode::Char('T') => Some((0, None)),
+                // Current song itself stays in place.
+                KeyCode::Char('N') => queue
+                    .ids
+                    .iter()
+                    .position(|&id| Some(id) == queue.current)
+                    .map(|pos| (pos as u32 + 1, queue.current)),
+                _ => None,
+            };
+            if let Some((to, current)) = destination {
+                let places: Vec<QueuePlace> =
+                    targets.iter().copied().filter(|place| Some(place.id) != current).collect();
+                let (list, start) = move_before(&places, to);
+                if !list.is_empty() {
+                    (action)(Action::Batch(list));
+                }
+                if let Some(moved) = places.iter().position(|place| place.pos as usize == at) {
+                    cursor.set(start as usize + moved);
+                }
+                return;
+            }
+
+            let is_shift = modifiers.contains(KeyModifiers::SHIFT);
+            let move_up = match code {
+                KeyCode::Char('K') => Some(true),
+                KeyCode::Up if is_shift => Some(true),
+                KeyCode::Char('J') => Some(false),
+                KeyCode::Down if is_shift => Some(false),
+                _ => None,
+            };
+            // Songs move together by one place, or not at all, and the cursor follows them.
+            match (move_up, targets.first(), targets.last()) {
+                (Some(true), Some(first), _) if first.pos > 0 => {
+                    let list = targets.iter().fold(CommandList::new(), |list, place| {
+                        list.move_id(place.id, place.pos - 1)
+                    });
+                    (action)(Action::Batch(list));
+                    cursor.set(at.saturating_sub(1));
+                    return;
+                },
+                (Some(false), _, Some(end)) if (end.pos as usize) < last => {
+                    let list = targets.iter().rev().fold(CommandList::new(), |list, place| {
+                        list.move_id(place.id, place.pos + 1)
+                    });
+                    (action)(Action::Batch(list));
+                    cursor.set(at + 1);
+                    return;
+                },
+                (Some(_), ..) => return,
+                (None, ..) => {},
+            }
+
+            let place = queue.place(at);
+            match (code, place) {
+                (KeyCode::Enter, Some(place)) => (action)(Action::Play(place.id)),
+                // Cursor stays in place, on the song after the removed ones.
+                (KeyCode::Char('d') | KeyCode::Delete, _) if !targets.is_empty() => {
+                    let list = targets
+                        .iter()
+                        .fold(CommandList::new(), |list, place| list.delete_id(place.id));
+                    (action)(Action::Batch(list));
+                    selection.write().clear();
+                },
+                // Priorities of songs which aren't loaded are unknown, so they're left as is.
+                (KeyCode::Char('+') | KeyCode::Char('-'), _) if !targets.is_empty() => {
+                    let loaded =
+                        targets.iter().filter(|place| queue.songs[place.pos as usize].is_some());
+                    let list = loaded.fold(CommandList::new(), |list, place| {
+                        let prio = match code {
+                            KeyCode::Char('+') => place.prio.saturating_add(PRIORITY_STEP),
+                            _ => place.prio.saturating_sub(PRIORITY_STEP),
+                        };
+                        list.prio_id(prio, place.id)
+                    });
+                    if !list.is_empty() {
+                        (action)(Action::Batch(list));
+                    }
+                },
+                // Songs with the highest priority play next, even in random mode.
+                (KeyCode::Char('P'), _) if !targets.is_empty() => {
+                    let list = targets
+                        .iter()
+                        .fold(CommandList::new(), |list, place| list.prio_id(u8::MAX, place.id));
+                    (action)(Action::Batch(list));
+                },
+                (KeyCode::Char('='), _) if !targets.is_empty() => {
+                    input.set(Some(Input::Priority(String::new())));
+                    text_input.set(true);
+                },
+                (KeyCode::Char('p'), _) => show_priority.set(!show_priority.get()),
+                (KeyCode::Char('c'), _) if !targets.is_empty() => {
+                    (action)(Action::Batch(crop(&targets, queue.len() as u32)));
+                    selection.write().clear();
+                    cursor.set(0);
+                },
+                (KeyCode::Char('X'), _) if queue.len() > 0 => {
+                    (action)(Action::Batch(CommandList::new().clear()));
+                    selection.write().clear();
+                },
+                (KeyCode::Char('S'), _) if queue.len() > 1 => {
+                    (action)(Action::Batch(CommandList::new().shuffle()));
+                },
+                (KeyCode::Char('u'), _) => (action)(Action::Undo),
+                (KeyCode::Char('U'), _) => (action)(Action::Redo),
+                (KeyCode::Char('s'), _) if !targets.is_empty() => {
+                    input.set(Some(Input::Playlist(String::new())));
+                    text_input.set(true);
+                },
+                // Filter matches every song, so all of them are loaded.
+                (KeyCode::Char('/'), _) => {
+                    let mut opened = Filter::default();
+                    opened.refresh(&queue.songs);
+                    filter.set(Some(opened));
+                    text_input.set(true);
+                    (load_all)(queue.missing(0..queue.len()));
+                },
+                (KeyCode::Char(' '), Some(place)) => {
+                    selection.write().toggle(place.id);
+                    cursor.set((at + 1).min(last));
+                },
+                (KeyCode::Char('v') | KeyCode::Char('V'), _) => {
+                    selection.write().toggle_visual(&queue.ids, at);
+                },
+                (KeyCode::Char('A'), _) => selection.write().select_all(&queue.ids),
+                (KeyCode::Char('I'), _) => selection.write().invert(&queue.ids, at),
+                (KeyCode::Esc, _) => {
+                    let mut selection = selection.write();
+                    if !selection.cancel_visual() {
+                        selection.clear();
+                    }
+                },
+                (KeyCode::Up | KeyCode::Char('k'), _) => cursor.set(at.saturating_sub(1)),
+                (KeyCode::Down | KeyCode::Char('j'), _) => cursor.set((at + 1).min(last)),
+                (KeyCode::PageUp, _) => cursor.set(at.saturating_sub(page)),
+                (KeyCode::PageDown, _) => cursor.set((at + page).min(last)),
+                (KeyCode::Home | KeyCode::Char('g'), _) => cursor.set(0),
+                (KeyCode::End | KeyCode::Char('G'), _) => cursor.set(last),
+                _ => {},
+            }
+        },
+        _ => {},
+    });
+
+    let mut drag: State<Option<Drag>> = hooks.use_state_default();
+    hooks.use_local_terminal_events(move |event| match event {
+        TerminalEvent::FullscreenMouse(FullscreenMouseEvent { kind, row, .. }) => {
+            let queue = queue.read();
+            let last = queue.len().saturating_sub(1);
+            // Table starts after the header, and dragging over the header or the footer
+            // scrolls it.
+            let (top, bottom) = view.get();
+            let index = (top + row as usize).saturating_sub(1).min(last);
+            let is_row = row > 0 && row as usize <= bottom - top && filter.read().is_none();
+            match kind {
+                MouseEventKind::ScrollUp => cursor.set(cursor.get().saturating_sub(WHEEL_STEP)),
+                MouseEventKind::ScrollDown => cursor.set((cursor.get() + WHEEL_STEP).min(last)),
+                MouseEventKind::Down(_) if is_row && queue.len() > 0 => {
+                    cursor.set(index);
+                    drag.set(Some(Drag { from: index, over: index }));
+                },
+                MouseEventKind::Drag(_) => {
+                    if let Some(Drag { from, .. }) = drag.get() {
+                        drag.set(Some(Drag { from, over: index }));
+                        cursor.set(index);
+                    }
+                },
+                MouseEventKind::Up(_) => {
+                    let Some(Drag { from, over }) = drag.get() else {
+                        return;
+                    };
+                    drag.set(None);
+                    let (Some(dragged), Some(target)) = (queue.place(from), queue.place(over))
+                    else {
+                        return;
+                    };
+                    if from == over {
+                        return;
+                    }
+
+                    // Selected songs are dragged together, when one of them is.
+                    let selection = selection.read();
+                    let places: Vec<QueuePlace> = match selection.contains(dragged.id) {
+                        true => (0..queue.len())
+                            .filter(|&index| selection.contains(queue.ids[index]))
+                            .filter_map(|index| queue.place(index))
+                            .collect(),
+                        false => vec![dragged],
+                    };
+                    let to = if over > from { target.pos + 1 } else { target.pos };
+                    let (list, start) = move_before(&places, to);
+                    if !list.is_empty() {
+                        (drag_action)(Action::Batch(list));
+                    }
+                    if let Some(moved) = places.iter().position(|place| place.id == dragged.id) {
+                        cursor.set(start as usize + moved);
+                    }
+                },
+                _ => {},
+            }
+        },
+        _ => {},
+    });
+
+    // Matches are updated as the query is typed, rather than on every render.
+    let on_query = move |query| {
+        if let Some(filter) = &mut *filter.write() {
+            filter.set_query(query, &queue.read().songs);
+        }
+    };
+    // Songs are loaded as they come into view, unless filtered, since then all of them are.
+    let on_missing = move |shown: Range<usize>| {
+        if filter.read().is_none() {
+            (load)(queue.read().missing(shown));
+        }
+    };
+
+    let queue_len = queue.read().len();
+    if queue_len == 0 {
+        return element! {
+            View(
+                width: Percent(100.0),
+                height: Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+            ) {
+                Text(content: "Queue is empty")
+            }
+        };
+    }
+
+    let at = cursor.get().min(queue_len - 1);
+    // Columns of tags which the server doesn't send, like ones left out of its
+    // `metadata_to_use`, are hidden.
+    let capabilities = ctx.mpd.capabilities();
+    let columns: Vec<Column> = visible_columns(&config.columns, show_priority.get())
+        .into_iter()
+        .filter(|column| column.is_available(|tag| capabilities.has_tag(tag)))
+        .collect();
+    let range = selection.read().visual_range(&queue.read().ids, at);
+    let filtered = filter.read().as_ref().map(|filter| filter.matches().len());
+    // Rows of the table are songs or their matches, and the best match is under the cursor
+    // when filtered.
+    let render_row = {
+        let columns = columns.clone();
+        move |index: usize| {
+            let filter = filter.read();
+            let (pos, is_cursor) = match &*filter {
+                Some(filter) => (*filter.matches().get(index)?, index == 0),
+                None => (index, index == at),
+            };
+            let queue = queue.read();
+            let song = queue.songs.get(pos)?.as_ref()?;
+            let (color, weight) = match queue.current.is_some_and(|id| id == queue.ids[pos]) {
+                true => (Some(Color::Magenta), Weight::Bold),
+                false => (None, Weight::Normal),
+            };
+            let is_selected = selection.read().contains(queue.ids[pos])
+                || range.as_ref().is_some_and(|range| range.contains(&pos));
+            let background = match (is_cursor, is_selected) {
+                (true, true) => Some(Color::Blue),
+                (true, false) => Some(Color::DarkGrey),
+                (false, true) => Some(Color::DarkBlue),
+                (false, false) => None,
+            };
+            let row = element! {
+                View(flex_direction: FlexDirection::Row, gap: 1, background_color: background) {
+                    #(columns.iter().map(|column| {
+                        let value = column.value(song);
+                        let highlights = match &*filter {
+                            Some(filter) => filter.highlights(&value),
+                            None => Vec::new(),
+                        };
+                        cell(column, value, &highlights, color, weight)
+                    }))
+                }
+            };
+            Some(row.into_any())
+        }
+    };
+
+    let selection = selection.read();
+    let footer = match (&*filter.read(), &*input.read()) {
+        (Some(filter), _) => element! {
+            View(flex_direction: FlexDirection::Row, gap: 1) {
+                Text(weight: Weight::Bold, content: "/")
+                View(flex_grow: 1.0) {
+                    TextInput(
+                        has_focus: true,
+                        value: filter.query().to_owned(),
+                        on_change: on_query,
+                    )
+                }
+                Text(
+                    weight: Weight::Light,
+                    content: format!("{}/{queue_len}", filter.matches().len()),
+                )
+            }
+        }
+        .into_any(),
+        (None, Some(typed)) => element! {
+            View(flex_direction: FlexDirection::Row, gap: 1) {
+                Text(weight: Weight::Bold, content: typed.prompt())
+                TextInput(
+                    has_focus: true,
+                    value: typed.text().to_owned(),
+                    on_change: move |text| {
+                        let typed = input.read().as_ref().map(|typed| typed.with_text(text));
+                        input.set(typed);
+                    },
+                )
+            }
+        }
+        .into_any(),
+        (None, None) => {
+            let mode = match (selection.is_visual(), selection.len()) {
+                (true, _) => "-- VISUAL -- ".to_owned(),
+                (false, 0) => String::new(),
+                (false, selected) => format!("{selected} selected, "),
+            };
+            element! {
+                Text(
+                    weight: Weight::Light,
+                    content: format!(
+                        "{mode}Space: select, V: range, A: all, I: invert, d: remove, \
+                        J/K: move, T: to top, N: after current, +/-/=: priority, \
+                        P: play next, p: priority column, s: add to playlist, /: filter, \
+                        c: crop, X: clear, S: shuffle, u/U: undo/redo",
+                    ),
+                )
+            }
+            .into_any()
+        },
+    };
+
+    element! {
+        View(width: Percent(100.0), height: Percent(100.0), flex_direction: FlexDirection::Column) {
+            View(flex_direction: FlexDirection::Row, gap: 1) {
+                #(columns.iter().map(|column| {
+                    cell(column, column.header().to_owned(), &[], None, Weight::Bold)
+                }))
+            }
+            View(flex_grow: 1.0) {
+                VirtualList(
+                    len: filtered.unwrap_or(queue_len),
+                    cursor: if filtered.is_some() { 0 } else { at },
+                    render_row,
+                    on_missing,
+                    on_scroll: move |shown: Range<usize>| view.set((shown.start, shown.end)),
+                )
+            }
+            #(footer)
+        }
+    }
+}
+
+/// Configured `columns`, with the priority column added after the position or removed.
+fn visible_columns(columns: &[Column], show_priority: bool) -> Vec<Column> {
+    let is_priority = |column: &&Column| column.source == Source::Priority;
+    match (show_priority, columns.iter().any(|column| is_priority(&column))) {
+        // Configured one keeps its place.
+        (true, true) => columns.to_vec(),
+        (true, false) => {
+            let mut visible = columns.to_vec();
+            let at = visible.iter().position(|column| column.source == Source::Position);
+            visible.insert(at.map_or(0, |at| at + 1), Column::priority());
+            visible
+        },
+        (false, _) => columns.iter().filter(|column| !is_priority(column)).cloned().collect(),
+    }
+}
+
+/// Commands to move songs at `places`, in the queue order, next to each other before the
+/// position `to`, and the position where the first of them ends up.
+fn move_before(places: &[QueuePlace], to: u32) -> (CommandList, u32) {
+    let above = places.partition_point(|place| place.pos < to);
+    let start = to - above as u32;
+    let Some(first) = places.first() else {
+        return (CommandList::new(), start);
+    };
+
+    let is_range = places.windows(2).all(|pair| pair[0].pos + 1 == pair[1].pos);
+    if is_range {
+        let range = first.pos..first.pos + places.len() as u32;
+        let list = match first.pos == start {
+            true => CommandList::new(),
+            false => CommandList::new().move_range(range, start),
+        };
+        return (list, start);
+    }
+
+    // Songs from above go down starting from the last one, and songs from below go up
+    // starting from the first one, so already moved songs stay in place.
+    let (above, below) = places.split_at(above);
+    let list = above.iter().rev().enumerate().fold(CommandList::new(), |list, (index, place)| {
+        list.move_id(place.id, to - 1 - index as u32)
+    });
+    let list = below
+        .iter()
+        .enumerate()
+        .fold(list, |list, (index, place)| list.move_id(place.id, to + index as u32));
+    (list, start)
+}
+
+/// Commands to delete every song of the queue with `len` songs but ones at `kept` places,
+/// in the queue order. Gaps are deleted from the end, so positions before them stay.
+fn crop(kept: &[QueuePlace], len: u32) -> CommandList {
+    let starts = [0].into_iter().chain(kept.iter().map(|place| place.pos + 1));
+    let ends = kept.iter().map(|place| place.pos).chain([len]);
+    let gaps: Vec<Range<u32>> = starts.zip(ends).map(|(start, end)| start..end).collect();
+    let gaps = gaps.into_iter().rev().filter(|gap| !gap.is_empty());
+    gaps.fold(CommandList::new(), |list, gap| list.delete_range(gap))
+}
+
+/// Single cell of the `column`, cut to the column width, with characters at `highlights`
+/// emphasized.
+fn cell(
+    column: &Column,
+    content: String,
+    highlights: &[usize],
+    color: Option<Color>,
+    weight: Weight,
+) -> AnyElement<'static> {
+    let (width, grow) = match column.width {
+        Width::Fixed(width) => (width as u32, 0.0),
+        Width::Flex { flex } => (0, flex as f32),
+    };
+    if highlights.is_empty() {
+        return element! {
+            View(width, flex_grow: grow, flex_shrink: 0.0, overflow: Overflow::Hidden) {
+                Text(content, color, weight, wrap: TextWrap::NoWrap, align: column.text_align())
+            }
+        }
+        .into_any();
+    }
+
+    // Runs of matched and not matched characters.
+    let mut contents = Vec::new();
+    let mut run = String::new();
+    let mut is_match = false;
+    let mut push_run = |run: String, is_match: bool| {
+        let part = match (is_match, color) {
+            (true, _) => MixedTextContent::new(run).color(Color::Yellow).weight(Weight::Bold),
+            (false, Some(color)) => MixedTextContent::new(run).color(color).weight(weight),
+            (false, None) => MixedTextContent::new(run).weight(weight),
+        };
+        contents.push(part);
+    };
+    for (index, c) in content.chars().enumerate() {
+        let matched = highlights.binary_search(&index).is_ok();
+        if matched != is_match && !run.is_empty() {
+            push_run(std::mem::take(&mut run), is_match);
+        }
+        is_match = matched;
+        run.push(c);
+    }
+    push_run(run, is_match);
+
+    element! {
+        View(width, flex_grow: grow, flex_shrink: 0.0, overflow: Overflow::Hidden) {
+            MixedText(contents, wrap: TextWrap::NoWrap, align: column.text_align())
+        }
+    }
+    .into_any()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        crop, fetch_changes,
+        history::{self, History, Replayed},
+        Queue, WINDOW,
+    };
+    use crate::mpd::{
+        mock::{MockServer, MockState},
+        Command, CommandList, MpdClient,
+    };
+
+    /// Bring ids of the `queue` up to date, like [`super::QueueScreen`] does.
+    async fn sync(queue: &mut Queue, mpd: &MpdClient) {
+        let mut client = mpd.bind().await.unwrap();
+        let changes = fetch_changes(&mut client, queue.version).await.unwrap();
+        assert!(queue.apply(changes));
+    }
+
+    /// Load songs of the `queue` at positions in the `range`, like the table does for
+    /// songs in view.
+    async fn load(queue: &mut Queue, mpd: &MpdClient, range: std::ops::Range<usize>) {
+        let mut client = mpd.bind().await.unwrap();
+        let songs = client.playlist_info_ranges(&queue.missing(range)).await.unwrap();
+        let version = queue.version;
+        assert!(queue.fill(version, songs));
+    }
+
+    /// Files, ids, positions and priorities of songs, to compare the queue with the server.
+    fn assert_converged(queue: &Queue, server: &MockServer) {
+        let mut expected = Vec::new();
+        server.update(|state| {
+            for song in &state.queue {
+                let value = |key: &str| {
+                    let pair = song.iter().find(|(k, _)| k == key);
+                    pair.map(|(_, value)| value.clone()).unwrap_or_else(|| "0".to_owned())
+                };
+                let number = |key: &str| value(key).parse().unwrap();
+                expected.push((value("file"), number("Id"), number("Pos"), number("Prio") as u8));
+            }
+        });
+        let actual: Vec<(String, u32, u32, u8)> = queue
+            .songs
+            .iter()
+            .zip(&queue.ids)
+            .map(|(song, &id)| {
+                let song = song.as_ref().expect("Songs are loaded");
+                let place = song.place.unwrap_or_default();
+                assert_eq!(place.id, id);
+                (song.file.clone(), place.id, place.pos, place.prio)
+            })
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    /// Files and priorities of songs in the queue of the `server`.
+    fn snapshot(server: &MockServer) -> Vec<(String, u8)> {
+        let mut songs = Vec::new();
+        server.update(|state| {
+            for song in &state.queue {
+                let value = |key: &str| song.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+                let prio = value("Prio").map_or(0, |prio| prio.parse().unwrap());
+                songs.push((value("file").unwrap().clone(), prio));
+            }
+        });
+        songs
+    }
+
+    /// Undo or redo the last edit of the `history`, checking it's allowed.
+    async fn replay(history: &mut History, mpd: &MpdClient, is_undo: bool) {
+        let taken = match is_undo {
+            true => history.take_undo(),
+            false => history.take_redo(),
+        };
+        let taken = taken.expect("History has the edit");
+        let mut client = mpd.bind().await.unwrap();
+        match history::replay(&mut client, &taken).await.unwrap() {
+            Replayed::Done(version) => history.finish(taken, version),
+            replayed => panic!("Queue isn't changed by others, but it's {replayed:?}"),
+        }
+    }
+
+    fn start_server(songs: usize) -> MockServer {
+        let mut state = MockState::default();
+        for index in 0..songs {
+            state.add_song(&format!("{index}.flac"), &[("Title", &index.to_string())]);
+        }
+        MockServer::start(state).unwrap()
+    }
+
+    /// Lines of `playlistinfo` requests received by the `server` after the first `skip`.
+    fn loaded(server: &MockServer, skip: usize) -> Vec<String> {
+        let received = server.received();
+        received[skip..].iter().filter(|line| line.starts_with("playlistinfo")).cloned().collect()
+    }
+
+    #[test]
+    fn loads_songs_by_windows_in_view() {
+        let len = 3 * WINDOW + 10;
+        let server = start_server(len);
+
+        smol::block_on(async {
+            let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+            let mut queue = Queue::default();
+            sync(&mut queue, &mpd).await;
+            assert_eq!(queue.len(), len);
+            assert!(queue.songs.iter().all(Option::is_none));
+            assert!(loaded(&server, 0).is_empty());
+
+            // Only the window in view is loaded, and only once.
+            load(&mut queue, &mpd, 10..40).await;
+            load(&mut queue, &mpd, 20..50).await;
+            assert_eq!(loaded(&server, 0), [format!("playlistinfo \"0:{WINDOW}\"")]);
+            assert!(queue.songs[..WINDOW].iter().all(Option::is_some));
+            assert!(queue.songs[WINDOW..].iter().all(Option::is_none));
+
+            load(&mut queue, &mpd, 0..len).await;
+            assert_converged(&queue, &server);
+
+            // Only windows with changed songs are loaded again.
+            let requests = server.received().len();
+            server.update(|state| {
+                state.move_song(1, 3);
+                state.set_priority(WINDOW * 2 + 5, 200);
+            });
+            sync(&mut queue, &mpd).await;
+            load(&mut queue, &mpd, 0..len).await;
+            assert_converged(&queue, &server);
+            let expected = [
+                format!("playlistinfo \"0:{WINDOW}\""),
+                format!("playlistinfo \"{}:{}\"", WINDOW * 2, WINDOW * 3),
+            ];
+            assert_eq!(loaded(&server, requests), expected);
+
+            // New songs are loaded by the last window.
+            let requests = server.received().len();
+            server.update(|state| state.add_song("new.flac", &[]));
+            sync(&mut queue, &mpd).await;
+            load(&mut queue, &mpd, 0..len + 1).await;
+            assert_converged(&queue, &server);
+            let last = len / WINDOW * WINDOW;
+            assert_eq!(loaded(&server, requests), [format!("playlistinfo \"{last}:{}\"", len + 1)]);
+        });
+    }
+
+    #[test]
+    fn reloads_moved_songs_with_changed_priority() {
+        let len = 4 * WINDOW;
+        let server = start_server(len);
+
+        smol::block_on(async {
+            let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+            let mut queue = Queue::default();
+            sync(&mut queue, &mpd).await;
+            load(&mut queue, &mpd, 0..len).await;
+
+            // Every song is shifted, and the moved ones get new priorities in the same sync.
+            server.update(|state| {
+                state.move_songs(0..WINDOW, len - WINDOW);
+                state.set_priority(len - 1, 100);
+                state.move_song(len - 1, 0);
+                state.set_priority(WINDOW, 50);
+            });
+            sync(&mut queue, &mpd).await;
+            load(&mut queue, &mpd, 0..len).await;
+            assert_converged(&queue, &server);
+        });
+    }
+
+    #[test]
+    fn drops_songs_loaded_before_changes() {
+        let server = start_server(10);
+
+        smol::block_on(async {
+            let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+            let mut queue = Queue::default();
+            sync(&mut queue, &mpd).await;
+            let version = queue.version;
+            let ranges = queue.missing(0..10);
+            let songs = mpd.bind().await.unwrap().playlist_info_ranges(&ranges).await.unwrap();
+
+            server.update(|state| state.set_priority(2, 100));
+            sync(&mut queue, &mpd).await;
+            assert!(!queue.fill(version, songs));
+            assert!(queue.songs.iter().all(Option::is_none));
+        });
+    }
+
+    #[test]
+    fn converges_after_random_changes() {
+        let server = start_server(50);
+
+        smol::block_on(async {
+            let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+            let mut queue = Queue::default();
+            sync(&mut queue, &mpd).await;
+
+            // Simple deterministic generator, so failures are reproducible.
+            let mut seed = 7u64;
+            let mut random = move |below: usize| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (seed >> 33) as usize % below.max(1)
+            };
+            for round in 0..100 {
+                server.update(|state| {
+                    for change in 0..random(5) {
+                        let len = state.queue.len();
+                        match random(4) {
+                            0 => state.add_song(&format!("{round}-{change}.flac"), &[]),
+                            1 if len > 0 => state.delete_song(random(len)),
+                            2 if len > 0 => state.move_song(random(len), random(len)),
+                            3 if len > 0 => state.set_priority(random(len), random(256) as u8),
+                            _ => {},
+                        }
+                    }
+                });
+                sync(&mut queue, &mpd).await;
+                let len = queue.len();
+                load(&mut queue, &mpd, 0..len).await;
+                assert_converged(&queue, &server);
+            }
+        });
+    }
+
+    #[test]
+    fn undoes_and_redoes_queue_edits() {
+        smol::block_on(async {
+            for edit in 0..9 {
+                let server = start_server(10);
+                server.update(|state| state.set_priority(4, 50));
+                let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+                let mut queue = Queue::default();
+                sync(&mut queue, &mpd).await;
+                let place = |pos: usize| queue.place(pos).unwrap();
+
+                let list = match edit {
+                    0 => CommandList::new().delete_id(place(7).id).delete_id(place(2).id),
+                    1 => crop(&[place(1), place(4), place(5)], 10),
+                    2 => CommandList::new().move_range(2..5, 6),
+                    3 => CommandList::new().move_id(place(0).id, 9).move_id(place(3).id, 1),
+                    4 => CommandList::new().prio_id(200, place(4).id).prio_id(10, place(8).id),
+                    5 => CommandList::new().clear(),
+                    6 => CommandList::new().shuffle().move_id(place(2).id, 0),
+                    7 => CommandList::new().push(Command::new("move").arg(2).arg(6)),
+                    _ => CommandList::new().add_id("new.flac", 3).add_id("last.flac", 11),
+                };
+                let before = snapshot(&server);
+                let mut history = History::default();
+                let recorded = history::run(&mut mpd.bind().await.unwrap(), &list).await;
+                history.push(recorded.unwrap());
+                let after = snapshot(&server);
+                assert_ne!(before, after, "Edit {edit} changes the queue");
+
+                for (is_undo, expected) in [(true, &before), (false, &after), (true, &before)] {
+                    replay(&mut history, &mpd, is_undo).await;
+                    assert_eq!(snapshot(&server), *expected, "Edit {edit}, undo {is_undo}");
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn undoes_edits_in_order() {
+        let server = start_server(10);
+
+        smol::block_on(async {
+            let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+            let mut queue = Queue::default();
+            sync(&mut queue, &mpd).await;
+            let lists = [
+                CommandList::new().prio_id(100, queue.place(5).unwrap().id),
+                CommandList::new().move_range(4..7, 0),
+                crop(&[queue.place(0).unwrap(), queue.place(5).unwrap()], 10),
+                CommandList::new().shuffle(),
+            ];
+
+            let mut history = History::default();
+            let mut snapshots = vec![snapshot(&server)];
+            for list in &lists {
+                let recorded = history::run(&mut mpd.bind().await.unwrap(), list).await;
+                history.push(recorded.unwrap());
+                snapshots.push(snapshot(&server));
+            }
+            // Failed replay is put back as it was.
+            let taken = history.take_undo().unwrap();
+            history.put_back(taken);
+            for expected in snapshots.iter().rev().skip(1) {
+                replay(&mut history, &mpd, true).await;
+                assert_eq!(snapshot(&server), *expected);
+            }
+            assert!(history.take_undo().is_none());
+            for expected in &snapshots[1..] {
+                replay(&mut history, &mpd, false).await;
+                assert_eq!(snapshot(&server), *expected);
+            }
+            assert!(history.take_redo().is_none());
+
+            // New edit drops undone ones.
+            replay(&mut history, &mpd, true).await;
+            let recorded = history::run(&mut mpd.bind().await.unwrap(), &lists[3]).await;
+            history.push(recorded.unwrap());
+            assert!(history.take_redo().is_none());
+        });
+    }
+
+    #[test]
+    fn refuses_undo_after_changes_by_others() {
+        let server = start_server(10);
+
+        smol::block_on(async {
+            let mpd = MpdClient::new(server.address(), None::<&str>).await.unwrap();
+            let mut history = History::default();
+            let clear = CommandList::new().clear();
+            let recorded = history::run(&mut mpd.bind().await.unwrap(), &clear).await;
+            history.push(recorded.unwrap());
+
+            server.update(|state| state.add_song("other.flac", &[]));
+            let taken = history.take_undo().unwrap();
+            // Undo is allowed by the commands it sends, not by the cleared ones.
+            assert_eq!(taken.commands(), ["addid"; 10]);
+            let replayed = history::replay(&mut mpd.bind().await.unwrap(), &taken).await.unwrap();
+            assert_eq!(replayed, Replayed::Refused);
+            assert_eq!(snapshot(&server), [("other.flac".to_owned(), 0)]);
+
+            // Edits made after that are recorded from scratch.
+            let recorded = history::run(&mut mpd.bind().await.unwrap(), &clear).await;
+            history.push(recorded.unwrap());
+            replay(&mut history, &mpd, true).await;
+            assert_eq!(snapshot(&server), [("other.flac".to_owned(), 0)]);
+            assert!(history.take_undo().is_none());
+        });
+    }
+}
@@ -0,0 +1,295 @@
+//! Columns of the queue table, configurable in the config file.
+
+use iocraft::prelude::TextAlign;
+use serde::Deserialize;
+
+use crate::{components::format_duration, mpd::Song};
+
+/// Column of the queue table, from the `[[queue.columns]]` array of the config file:
+///
+/// ```toml
+/// [[queue.columns]]
+/// source = "AlbumArtist"
+/// header = "Album artist"
+/// width = { flex = 2 }
+///
+/// [[queue.columns]]
+/// source = "duration"
+/// width = 8
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Column {
+    pub source: Source,
+    /// Sources to try in order when the song has no value from `source`. By default,
+    /// sort tags fall back to the tags they sort, `AlbumArtist` to `Artist`, and `Title`
+    /// to the stream name and then to the file path.
+    pub fallback: Option<Vec<Source>>,
+    #[serde(default)]
+    pub width: Width,
+    /// Alignment of values, numbers are aligned to the right by default.
+    pub align: Option<Align>,
+    /// Header label, the name of the source by default.
+    pub header: Option<String>,
+}
+
+impl Column {
+    fn new(source: Source, width: Width) -> Self {
+        Self { source, fallback: None, width, align: None, header: None }
+    }
+
+    /// Columns used unless the config file has its own.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(Source::Position, Width::Fixed(5)),
+            Self::new(Source::tag("Artist"), Width::Flex { flex: 2 }),
+            Self::new(Source::tag("Title"), Width::Flex { flex: 3 }),
+            Self::new(Source::tag("Album"), Width::Flex { flex: 2 }),
+            Self::new(Source::Duration, Width::Fixed(8)),
+        ]
+    }
+
+    /// Column with priorities of songs, shown on demand.
+    pub fn priority() -> Self {
+        Self::new(Source::Priority, Width::Fixed(4))
+    }
+
+    pub fn header(&self) -> &str {
+        self.header.as_deref().unwrap_or_else(|| self.source.name())
+    }
+
+    pub fn text_align(&self) -> TextAlign {
+        match self.align.unwrap_or_else(|| self.source.align()) {
+            Align::Left => TextAlign::Left,
+            Align::Center => TextAlign::Center,
+            Align::Right => TextAlign::Right,
+        }
+    }
+
+    /// Whether songs can have a value of the column, if only tags for which `has_tag`
+    /// is true are sent by the server.
+    pub fn is_available(&self, has_tag: impl Fn(&str) -> bool) -> bool {
+        let fallback = self.fallback.clone().unwrap_or_else(|| self.source.default_fallback());
+        std::iter::once(&self.source).chain(&fallback).any(|source| match source {
+            Source::Tag(name) => has_tag(name),
+            _ => true,
+        })
+    }
+
+    /// Value of the column for the `song`, or an empty string if it has none.
+    pub fn value(&self, song: &Song) -> String {
+        if let Some(value) = self.source.value(song) {
+            return value;
+        }
+        let value = match &self.fallback {
+            Some(fallback) => fallback.iter().find_map(|source| source.value(song)),
+            None => self.source.default_fallback().iter().find_map(|source| source.value(song)),
+        };
+        value.unwrap_or_default()
+    }
+}
+
+/// Where values of a [`Column`] come from. In the config file, it's one of `position`,
+/// `file`, `duration` or `priority`, and anything else is the name of an MPD tag.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum Source {
+    /// Position in the queue, starting from 1.
+    Position,
+    /// Path of the song file.
+    File,
+    Duration,
+    /// Priority in random mode, see `prio` in the MPD docs.
+    Priority,
+    /// Any MPD tag, like `Artist` or `Composer`, case insensitive.
+    Tag(String),
+}
+
+impl From<String> for Source {
+    fn from(name: String) -> Self {
+        match name.to_lowercase().as_str() {
+            "position" => Source::Position,
+            "file" => Source::File,
+            "duration" => Source::Duration,
+            "priority" => Source::Priority,
+            _ => Source::Tag(name),
+        }
+    }
+}
+
+impl Source {
+    fn tag(name: &str) -> Self {
+        Source::Tag(name.to_owned())
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Source::Position => "#",
+            Source::File => "File",
+            Source::Duration => "Time",
+            Source::Priority => "Prio",
+            Source::Tag(name) => name,
+        }
+    }
+
+    fn align(&self) -> Align {
+        match self {
+            Source::Position | Source::Duration | Source::Priority => Align::Right,
+            Source::File | Source::Tag(_) => Align::Left,
+        }
+    }
+
+    fn value(&self, song: &Song) -> Option<String> {
+        match self {
+            Source::Position => song.place.map(|place| (place.pos + 1).to_string()),
+            Source::File => Some(song.file.clone()),
+            Source::Duration => song
+                .duration
+                .and_then(|duration| chrono::Duration::from_std(duration).ok())
+                .map(format_duration),
+            Source::Priority => song.place.map(|place| place.prio.to_string()),
+            Source::Tag(name) => {
+                song.tag(name).filter(|value| !value.is_empty()).map(str::to_owned)
+            },
+        }
+    }
+
+    fn default_fallback(&self) -> Vec<Source> {
+        let Source::Tag(name) = self else {
+            return Vec::new();
+        };
+        let tags: &[&str] = match name.to_lowercase().as_str() {
+            "albumartist" => &["Artist"],
+            "albumartistsort" => &["AlbumArtist", "ArtistSort", "Artist"],
+            "artistsort" => &["Artist"],
+            "albumsort" => &["Album"],
+            "composersort" => &["Composer"],
+            "title" => &["Name"],
+            _ => &[],
+        };
+        let mut fallback: Vec<Source> = tags.iter().map(|tag| Source::tag(tag)).collect();
+        if name.eq_ignore_ascii_case("title") {
+            fallback.push(Source::File);
+        }
+        fallback
+    }
+}
+
+/// Width of a [`Column`]: a number of characters, like `width = 8`, or a share of the
+/// space left from fixed columns, like `width = { flex = 2 }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Width {
+    Fixed(u16),
+    Flex { flex: u16 },
+}
+
+impl Default for Width {
+    fn default() -> Self {
+        Width::Flex { flex: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Column, Source, Width};
+    use crate::mpd::Song;
+
+    /// Tags of a song, by their names.
+    type Tags<'a> = &'a [(&'a str, &'a str)];
+
+    fn column(config: &str) -> Result<Column, toml::de::Error> {
+        toml::from_str(config)
+    }
+
+    fn song(tags: Tags) -> Song {
+        let tags = tags.iter().map(|&(key, value)| (key.to_owned(), value.to_owned())).collect();
+        Song { file: "music/song.flac".to_owned(), tags, ..Song::default() }
+    }
+
+    #[test]
+    fn parses_sources_by_name() {
+        let cases = [
+            ("position", Source::Position, "#"),
+            ("POSITION", Source::Position, "#"),
+            ("File", Source::File, "File"),
+            ("duration", Source::Duration, "Time"),
+            ("priority", Source::Priority, "Prio"),
+            // Anything else is a tag, even one MPD doesn't know.
+            ("Composer", Source::tag("Composer"), "Composer"),
+            ("NoSuchTag", Source::tag("NoSuchTag"), "NoSuchTag"),
+        ];
+        for (name, source, header) in cases {
+            let column = column(&format!("source = {name:?}")).unwrap();
+            assert_eq!(column.source, source, "Source {name}");
+            assert_eq!(column.header(), header, "Source {name}");
+        }
+
+        let mystery = column("source = \"NoSuchTag\"\nheader = \"Mystery\"").unwrap();
+        assert_eq!(mystery.header(), "Mystery");
+        assert_eq!(mystery.value(&song(&[("Artist", "Someone")])), "");
+
+        // Source is required, and unknown keys are mistakes.
+        assert!(column("width = 8").is_err());
+        assert!(column("source = \"Title\"\ncolour = \"red\"").is_err());
+        assert!(column("source = \"Title\"\nalign = \"justify\"").is_err());
+    }
+
+    #[test]
+    fn parses_widths() {
+        let cases = [
+            ("source = \"Title\"", Some(Width::Flex { flex: 1 })),
+            ("source = \"Title\"\nwidth = 8", Some(Width::Fixed(8))),
+            ("source = \"Title\"\nwidth = { flex = 2 }", Some(Width::Flex { flex: 2 })),
+            ("source = \"Title\"\nwidth = -1", None),
+            ("source = \"Title\"\nwidth = \"wide\"", None),
+            ("source = \"Title\"\nwidth = { share = 2 }", None),
+        ];
+        for (config, width) in cases {
+            assert_eq!(column(config).ok().map(|column| column.width), width, "{config}");
+        }
+    }
+
+    #[test]
+    fn falls_back_to_other_sources() {
+        let cases: &[(&str, Tags, &str)] = &[
+            ("source = \"AlbumArtist\"", &[("AlbumArtist", "Band"), ("Artist", "Singer")], "Band"),
+            ("source = \"AlbumArtist\"", &[("Artist", "Singer")], "Singer"),
+            ("source = \"albumartistsort\"", &[("ArtistSort", "Singer, The")], "Singer, The"),
+            ("source = \"Title\"", &[("Name", "Radio")], "Radio"),
+            ("source = \"Title\"", &[("Title", "")], "music/song.flac"),
+            ("source = \"Composer\"", &[("Artist", "Singer")], ""),
+            // Fallback from the config replaces the default one.
+            ("source = \"Title\"\nfallback = [\"Album\"]", &[("Album", "Record")], "Record"),
+            ("source = \"Title\"\nfallback = []", &[], ""),
+            ("source = \"Composer\"\nfallback = [\"Artist\", \"file\"]", &[], "music/song.flac"),
+        ];
+        for &(config, tags, value) in cases {
+            assert_eq!(column(config).unwrap().value(&song(tags)), value, "{config}");
+        }
+    }
+
+    #[test]
+    fn is_available_with_any_source_sent() {
+        let has_tag = |tag: &str| ["Artist", "Title"].contains(&tag);
+        let cases = [
+            ("source = \"Artist\"", true),
+            ("source = \"Composer\"", false),
+            ("source = \"AlbumArtist\"", true),
+            ("source = \"AlbumArtist\"\nfallback = []", false),
+            ("source = \"Composer\"\nfallback = [\"Album\", \"Artist\"]", true),
+            ("source = \"duration\"", true),
+        ];
+        for (config, available) in cases {
+            assert_eq!(column(config).unwrap().is_available(has_tag), available, "{config}");
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Fuzzy filter of the queue, in the spirit of fzf.
+//!
+//! Query is split by whitespace into terms, and every term has to match artist, title,
+//! album or file of the song as a subsequence, ignoring case. Matches are scored by how
+//! close matched characters are to each other and to word boundaries.
+
+use std::cmp::Reverse;
+
+use crate::mpd::Song;
+
+const SCORE_MATCH: i32 = 16;
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_CONSECUTIVE: i32 = 4;
+const PENALTY_GAP_START: i32 = 3;
+const PENALTY_GAP: i32 = 1;
+
+/// Songs of the queue which match a query, best matches first.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    query: String,
+    /// Indices of matching songs in the queue.
+    matches: Vec<usize>,
+}
+
+impl Filter {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn matches(&self) -> &[usize] {
+        &self.matches
+    }
+
+    pub fn set_query(&mut self, query: String, songs: &[Option<Song>]) {
+        self.query = query;
+        self.refresh(songs);
+    }
+
+    /// Match the query against `songs` again, after the queue has changed or more songs
+    /// are loaded. Songs which aren't loaded yet don't match.
+    pub fn refresh(&mut self, songs: &[Option<Song>]) {
+        let terms = terms(&self.query);
+        let mut scored: Vec<(i32, usize)> = songs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, song)| {
+                let song = song.as_ref()?;
+                let mut total = 0;
+                for term in &terms {
+                    total += fields(song).filter_map(|field| fuzzy_match(term, field)).max()?.score;
+                }
+                Some((total, index))
+            })
+            .collect();
+        // Equally good matches stay in the queue order.
+        scored.sort_unstable_by_key(|&(score, index)| (Reverse(score), index));
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+    }
+
+    /// Positions of characters in `text` matched by any of the query terms.
+    pub fn highlights(&self, text: &str) -> Vec<usize> {
+        let mut positions: Vec<usize> = terms(&self.query)
+            .iter()
+            .filter_map(|term| fuzzy_match(term, text))
+            .flat_map(|found| found.positions)
+            .collect();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+}
+
+/// Lowercase terms of the `query`.
+fn terms(query: &str) -> Vec<Vec<char>> {
+    query.split_whitespace().map(|term| term.chars().map(lowercase).collect()).collect()
+}
+
+fn fields(song: &Song) -> impl Iterator<Item = &str> {
+    let tags = [song.artist.as_deref(), song.title.as_deref(), song.album.as_deref()];
+    tags.into_iter().flatten().chain([song.file.as_str()])
+}
+
+/// Lowercase `c` into a single character, the same way for the query and the text, so every
+/// character of the query matches a single character of the text.
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Occurrence of a term in a text.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Match {
+    score: i32,
+    /// Positions of matched characters.
+    positions: Vec<usize>,
+}
+
+/// Find the `term` in the `text` as a subsequence. Like fzf v1, the first occurrence is
+/// shortened from its end backwards, which is fast and usually good enough.
+fn fuzzy_match(term: &[char], text: &str) -> Option<Match> {
+    let last = term.len().checked_sub(1)?;
+
+    // Most of the texts don't match, so the first pass doesn't allocate.
+    let mut next = 0;
+    let mut end = None;
+    for (index, c) in text.chars().enumerate() {
+        if lowercase(c) == term[next] {
+            if next == last {
+                end = Some(index);
+                break;
+            }
+            next += 1;
+        }
+    }
+    let chars: Vec<char> = text.chars().take(end? + 1).collect();
+
+    let mut next = last;
+    let mut start = 0;
+    for (index, &c) in chars.iter().enumerate().rev() {
+        if lowercase(c) == term[next] {
+            if next == 0 {
+                start = index;
+                break;
+            }
+            next -= 1;
+        }
+    }
+
+    let mut found = Match { score: 0, positions: Vec::with_capacity(term.len()) };
+    let mut next = 0;
+    for (index, &c) in chars.iter().enumerate().skip(start) {
+        if next == term.len() || lowercase(c) != term[next] {
+            continue;
+        }
+        found.score += SCORE_MATCH;
+        if index == 0 || !chars[index - 1].is_alphanumeric() {
+            found.score += BONUS_BOUNDARY;
+        }
+        match found.positions.last() {
+            Some(&previous) if previous + 1 == index => found.score += BONUS_CONSECUTIVE,
+            Some(&previous) => {
+                found.score -= PENALTY_GAP_START + PENALTY_GAP * (index - previous - 2) as i32;
+            },
+            None => {},
+        }
+        found.positions.push(index);
+        next += 1;
+    }
+    Some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use crate::mpd::Song;
+
+    /// Artists and titles of songs in the queue.
+    type Songs<'a> = &'a [(&'a str, &'a str)];
+
+    fn song(artist: &str, title: &str) -> Option<Song> {
+        let tag = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+        Some(Song {
+            file: format!("{title}.flac"),
+            artist: tag(artist),
+            title: tag(title),
+            ..Song::default()
+        })
+    }
+
+    #[test]
+    fn orders_matches_by_score() {
+        let cases: &[(&str, Songs, &[usize])] = &[
+            // Consecutive characters at a word boundary come first.
+            ("abc", &[("", "xaxbxc"), ("", "abc"), ("", "xabc"), ("", "zzz")], &[1, 2, 0]),
+            // Equally good matches stay in the queue order.
+            ("song", &[("", "b song"), ("", "a song"), ("", "c song")], &[0, 1, 2]),
+            // Every term has to match, but not necessarily the same field.
+            ("beat yell", &[("Coldplay", "Yellow"), ("The Beatles", "Yellow Submarine")], &[1]),
+            ("BEAT", &[("", "the beatles")], &[0]),
+            // Query is lowercased just like the text, even when it's longer in lowercase.
+            ("İst", &[("", "İstanbul"), ("", "Istanbul")], &[0, 1]),
+            ("", &[("", "a"), ("", "b")], &[0, 1]),
+        ];
+        for &(query, songs, expected) in cases {
+            let songs: Vec<Option<Song>> =
+                songs.iter().map(|&(artist, title)| song(artist, title)).collect();
+            let mut filter = Filter::default();
+            filter.set_query(query.to_owned(), &songs);
+            assert_eq!(filter.matches(), expected, "Query {query:?}");
+        }
+    }
+
+    #[test]
+    fn skips_songs_not_loaded_yet() {
+        let mut songs = vec![None, song("", "abc")];
+        let mut filter = Filter::default();
+        filter.set_query("abc".to_owned(), &songs);
+        assert_eq!(filter.matches(), [1]);
+
+        songs[0] = song("", "abc");
+        filter.refresh(&songs);
+        assert_eq!(filter.matches(), [0, 1]);
+    }
+
+    #[test]
+    fn highlights_matched_characters() {
+        let cases: &[(&str, &str, &[usize])] = &[
+            ("abc", "xaxbxc", &[1, 3, 5]),
+            // First occurrence is shortened from its end.
+            ("ab", "a xab", &[3, 4]),
+            // Terms are highlighted together, overlapping or not.
+            ("ab c", "abxc", &[0, 1, 3]),
+            ("ab b", "ab", &[0, 1]),
+            ("ABC", "abc", &[0, 1, 2]),
+            // Positions are of characters, not bytes.
+            ("İs", "İstanbul", &[0, 1]),
+            ("zz", "abc", &[]),
+            ("", "abc", &[]),
+        ];
+        for &(query, text, expected) in cases {
+            let mut filter = Filter::default();
+            filter.set_query(query.to_owned(), &[]);
+            assert_eq!(filter.highlights(text), expected, "Query {query:?} in {text:?}");
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Local history of queue edits, to undo and redo them.
+//!
+//! Edits are recorded by positions of songs, from probes sent in the same command list
+//! as the edits, so the history doesn't depend on the queue shown on the screen. Positions
+//! are only valid for the version of the queue they're recorded in, so the history is
+//! replayed only while nobody else has changed the queue.
+//!
+//! MPD has no commands which run only for some version of the queue, so somebody else can
+//! still change it right before a replay. That's told by the version sent back with the
+//! replay, but the replay is already made by then.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::mpd::{Command, CommandList, MpdError, MpdGuard, MpdResult, Response, Song, Status};
+
+/// How many edits are kept to undo.
+const MAX_EDITS: usize = 100;
+
+/// Single change of the queue, by positions, which can be replayed both ways.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Add(Entry),
+    Delete(Entry),
+    /// Songs at positions in the range moved to start at the position `to`.
+    Move { range: Range<u32>, to: u32 },
+    Priority { pos: u32, from: u8, to: u8 },
+    /// Every song of the queue reordered, from ids in the old order to ids in the new one.
+    Reorder { from: Vec<u32>, to: Vec<u32> },
+}
+
+/// Song added to the queue or deleted from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    pos: u32,
+    file: String,
+    prio: u8,
+}
+
+impl Entry {
+    fn from_song(song: Song) -> Self {
+        let place = song.place.unwrap_or_default();
+        Self { pos: place.pos, file: song.file, prio: place.prio }
+    }
+}
+
+impl Step {
+    fn reverted(&self) -> Self {
+        match self {
+            Step::Add(entry) => Step::Delete(entry.clone()),
+            Step::Delete(entry) => Step::Add(entry.clone()),
+            Step::Move { range, to } => {
+                Step::Move { range: *to..to + range.len() as u32, to: range.start }
+            },
+            &Step::Priority { pos, from, to } => Step::Priority { pos, from: to, to: from },
+            Step::Reorder { from, to } => Step::Reorder { from: to.clone(), to: from.clone() },
+        }
+    }
+
+    fn append_to(&self, list: CommandList) -> CommandList {
+        match self {
+            Step::Add(Entry { pos, file, prio: 0 }) => list.add_id(file, *pos),
+            Step::Add(Entry { pos, file, prio }) => {
+                list.add_id(file, *pos).prio_range(*prio, *pos..pos + 1)
+            },
+            Step::Delete(Entry { pos, .. }) => list.delete_range(*pos..pos + 1),
+            Step::Move { range, to } => list.move_range(range.clone(), *to),
+            Step::Priority { pos, to, .. } => list.prio_range(*to, *pos..pos + 1),
+            Step::Reorder { from, to } => reorder(list, from, to),
+        }
+    }
+}
+
+/// Queue edit made by a single command list, like deleting the selection.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    steps: Vec<Step>,
+}
+
+impl Edit {
+    /// Append commands which make the edit again to the `list`.
+    fn redo(&self, list: CommandList) -> CommandList {
+        self.steps.iter().fold(list, |list, step| step.append_to(list))
+    }
+
+    /// Append commands which revert the edit to the `list`.
+    fn undo(&self, list: CommandList) -> CommandList {
+        self.steps.iter().rev().fold(list, |list, step| step.reverted().append_to(list))
+    }
+}
+
+/// Edit run by [`run`], with versions of the queue around it.
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    /// Edit, or `None` if some of its commands can't be reverted.
+    edit: Option<Edit>,
+    before: u32,
+    after: u32,
+}
+
+/// Undo or redo taken from the [`History`], to be replayed by [`replay`].
+#[derive(Debug, Clone)]
+pub struct Replay {
+    edit: Edit,
+    is_undo: bool,
+    /// Version of the queue the replay is valid for.
+    version: u32,
+}
+
+impl Replay {
+    /// Names of the commands the replay sends, to check if they're allowed.
+    pub fn commands(&self) -> Vec<&'static str> {
+        self.append_to(CommandList::new()).commands().iter().map(Command::name).collect()
+    }
+
+    fn append_to(&self, list: CommandList) -> CommandList {
+        match self.is_undo {
+            true => self.edit.undo(list),
+            false => self.edit.redo(list),
+        }
+    }
+}
+
+/// What [`replay`] has done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replayed {
+    /// Replay is made, and the queue is left at the version.
+    Done(u32),
+    /// Nothing, since somebody else has changed the queue since the history was recorded.
+    Refused,
+    /// Replay is made, but somebody else had changed the queue right before it, so it may
+    /// have changed other songs than recorded.
+    Raced,
+}
+
+/// Edits of the queue which can be undone, and undone ones which can be redone.
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// Version of the queue after the last edit, undo or redo.
+    version: Option<u32>,
+}
+
+impl History {
+    /// Add the `recorded` edit, dropping older ones if somebody else has changed the queue
+    /// since them, and every undone one.
+    pub fn push(&mut self, recorded: Recorded) {
+        if self.version != Some(recorded.before) {
+            self.undo.clear();
+        }
+        self.redo.clear();
+        match recorded.edit {
+            Some(edit) => self.undo.push(edit),
+            None => self.undo.clear(),
+        }
+        if self.undo.len() > MAX_EDITS {
+            self.undo.remove(0);
+        }
+        self.version = Some(recorded.after);
+    }
+
+    /// Take the last edit to undo.
+    pub fn take_undo(&mut self) -> Option<Replay> {
+        let version = self.version?;
+        Some(Replay { edit: self.undo.pop()?, is_undo: true, version })
+    }
+
+    /// Take the last undone edit to make it again.
+    pub fn take_redo(&mut self) -> Option<Replay> {
+        let version = self.version?;
+        Some(Replay { edit: self.redo.pop()?, is_undo: false, version })
+    }
+
+    /// Put back the `replay`, which has left the queue at `version`, so it can be reverted
+    /// in turn.
+    pub fn finish(&mut self, replay: Replay, version: u32) {
+        match replay.is_undo {
+            true => self.redo.push(replay.edit),
+            false => self.undo.push(replay.edit),
+        }
+        self.version = Some(version);
+    }
+
+    /// Put back the `replay` which has failed, so it can be tried again. If it has changed
+    /// the queue in part, the next try is refused, since the version is different.
+    pub fn put_back(&mut self, replay: Replay) {
+        match replay.is_undo {
+            true => self.undo.push(replay.edit),
+            false => self.redo.push(replay.edit),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Run queue edits of the `list` at once, probing songs they change right before them,
+/// so they can be reverted.
+pub async fn run(client: &mut MpdGuard, list: &CommandList) -> MpdResult<Recorded> {
+    let probed = list.commands().iter().fold(CommandList::new().push(status()), |probed, command| {
+        let args = command.args();
+        let probed = match (command.name(), args) {
+            ("deleteid" | "moveid", [id, ..]) | ("prioid", [_, id]) => {
+                probed.push(Command::new("playlistid").arg(id))
+            },
+            ("delete", [range]) => probed.push(Command::new("playlistinfo").arg(range)),
+            ("clear", _) => probed.push(Command::new("playlistinfo")),
+            ("shuffle", _) => probed.push(Command::new("plchangesposid").arg(0)),
+            _ => probed,
+        };
+        let probed = probed.push(command.clone());
+        match command.name() {
+            "shuffle" => probed.push(Command::new("plchangesposid").arg(0)),
+            _ => probed,
+        }
+    });
+    let responses = client.batch(&probed.push(status())).await?;
+    let before = parse_status(responses.first())?;
+    let after = parse_status(responses.last())?;
+
+    // Commands have already run, so an edit which can't be recorded is only left out.
+    let probes = responses.into_iter().skip(1);
+    let edit = record(list, probes, before.queue_len).unwrap_or_else(|error| {
+        tracing::warn!("Queue edit can't be recorded to undo: {error}");
+        None
+    });
+    Ok(Recorded { edit, before: before.queue_version, after: after.queue_version })
+}
+
+/// Edit made by commands of the `list` from `responses` to them and their probes, or `None`
+/// if some of them can't be reverted. The queue has `len` songs before the edit.
+fn record(
+    list: &CommandList,
+    mut responses: impl Iterator<Item = Response>,
+    mut len: u32,
+) -> MpdResult<Option<Edit>> {
+    let mut next = move || {
+        responses.next().ok_or_else(|| MpdError::Parse("Missing response in the list".to_owned()))
+    };
+    let mut steps = Vec::new();
+    for command in list.commands() {
+        let args = command.args();
+        let number = |index: usize| args.get(index).and_then(|arg| arg.parse::<u32>().ok());
+        match (command.name(), number(0), number(1)) {
+            ("deleteid", ..) | ("delete", ..) | ("clear", ..) => {
+                let songs = Song::list_from_pairs(&next()?.pairs)?;
+                next()?;
+                // Songs are deleted from the end, so positions of the rest stay the same.
+                let deleted = songs.into_iter().rev().map(Entry::from_song).map(Step::Delete);
+                len -= deleted.len() as u32;
+                steps.extend(deleted);
+            },
+            ("moveid", _, Some(to)) => {
+                let from = probed_pos(next()?)?;
+                next()?;
+                steps.push(Step::Move { range: from..from + 1, to });
+            },
+            ("move", _, Some(to)) => {
+                next()?;
+                let range = parse_range(&args[0]).ok_or_else(|| parse_error(command))?;
+                steps.push(Step::Move { range, to });
+            },
+            ("prioid", Some(prio), _) => {
+                let song = Song::from_pairs(&next()?.pairs)?;
+                next()?;
+                let place = song.place.ok_or_else(|| parse_error(command))?;
+                let to = prio.min(u8::MAX as u32) as u8;
+                steps.push(Step::Priority { pos: place.pos, from: place.prio, to });
+            },
+            ("shuffle", ..) => {
+                let from = ids(next()?)?;
+                next()?;
+                steps.push(Step::Reorder { from, to: ids(next()?)? });
+            },
+            ("addid", ..) => {
+                let pos = number(1).unwrap_or(len);
+                next()?;
+                len += 1;
+                steps.push(Step::Add(Entry { pos, file: args[0].clone(), prio: 0 }));
+            },
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(Edit { steps }))
+}
+
+/// Replay the undo or redo, unless the queue has changed since the history was recorded.
+pub async fn replay(client: &mut MpdGuard, replay: &Replay) -> MpdResult<Replayed> {
+    // Checked on its own first, to refuse without changing anything.
+    if client.status().await?.queue_version != replay.version {
+        return Ok(Replayed::Refused);
+    }
+    let list = replay.append_to(CommandList::new().push(status())).push(status());
+    let responses = client.batch(&list).await?;
+    if parse_status(responses.first())?.queue_version != replay.version {
+        return Ok(Replayed::Raced);
+    }
+    Ok(Replayed::Done(parse_status(responses.last())?.queue_version))
+}
+
+fn status() -> Command {
+    Command::new("status")
+}
+
+fn parse_status(response: Option<&Response>) -> MpdResult<Status> {
+    let response = response.ok_or_else(|| MpdError::Parse("Missing status".to_owned()))?;
+    Status::from_pairs(&response.pairs)
+}
+
+/// Position of the song probed with `playlistid`.
+fn probed_pos(response: Response) -> MpdResult<u32> {
+    let place = Song::from_pairs(&response.pairs)?.place;
+    place.map(|place| place.pos).ok_or_else(|| MpdError::Parse("Song without position".to_owned()))
+}
+
+/// Ids of songs probed with `plchangesposid`, in the queue order.
+fn ids(response: Response) -> MpdResult<Vec<u32>> {
+    let ids = response.pairs.into_iter().filter(|(key, _)| key == "Id");
+    ids.map(|(_, id)| Ok(id.parse()?)).collect()
+}
+
+fn parse_error(command: &Command) -> MpdError {
+    MpdError::Parse(format!("Can't record {command}"))
+}
+
+/// Positions in a range argument, like `3:7`, or in a single position one.
+fn parse_range(arg: &str) -> Option<Range<u32>> {
+    match arg.split_once(':') {
+        Some((start, end)) => Some(start.parse().ok()?..end.parse().ok()?),
+        None => arg.parse().ok().map(|pos: u32| pos..pos + 1),
+    }
+}
+
+/// Commands moving songs by positions from the `current` order of ids to the `target` one,
+/// which has the same ids.
+fn reorder(mut list: CommandList, current: &[u32], target: &[u32]) -> CommandList {
+    let indices: HashMap<u32, usize> =
+        current.iter().enumerate().map(|(index, &id)| (id, index)).collect();
+    // Fenwick tree of songs which aren't in place yet, by their indices in `current`.
+    let mut tree = vec![0; current.len() + 1];
+    let add = |tree: &mut [i32], index: usize, delta: i32| {
+        let mut node = index + 1;
+        while node < tree.len() {
+            tree[node] += delta;
+            node += node & node.wrapping_neg();
+        }
+    };
+    let count_before = |tree: &[i32], index: usize| {
+        let (mut node, mut count) = (index, 0);
+        while node > 0 {
+            count += tree[node];
+            node -= node & node.wrapping_neg();
+        }
+        count as u32
+    };
+    for index in 0..current.len() {
+        add(&mut tree, index, 1);
+    }
+
+    // Songs are put in place one by one from the start, and the rest keep their order.
+    for (pos, id) in (0u32..).zip(target) {
+        let Some(&index) = indices.get(id) else {
+            continue;
+        };
+        let from = pos + count_before(&tree, index);
+        add(&mut tree, index, -1);
+        if from != pos {
+            list = list.move_range(from..from + 1, pos);
+        }
+    }
+    list
+}
@@ -0,0 +1,184 @@
+//! Songs selected in the queue for batch actions.
+
+use std::{collections::HashSet, ops::RangeInclusive};
+
+/// Songs selected for batch actions, kept by song id, so the selection survives changes
+/// of the queue by other clients.
+#[derive(Debug, Default, Clone)]
+pub struct Selection {
+    ids: HashSet<u32>,
+    /// Song where the visual range starts, it ends at the cursor.
+    anchor: Option<u32>,
+}
+
+impl Selection {
+    /// Number of selected songs, without the visual range.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_visual(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub fn toggle(&mut self, id: u32) {
+        if !self.ids.remove(&id) {
+            self.ids.insert(id);
+        }
+    }
+
+    /// Start the visual range at the `cursor`, or add the range to the selection if it's
+    /// already started.
+    pub fn toggle_visual(&mut self, ids: &[u32], cursor: usize) {
+        if self.anchor.is_some() {
+            self.commit_visual(ids, cursor);
+        } else {
+            self.anchor = ids.get(cursor).copied();
+        }
+    }
+
+    /// Drop the visual range, if there is one. Returns whether there was.
+    pub fn cancel_visual(&mut self) -> bool {
+        self.anchor.take().is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.anchor = None;
+    }
+
+    pub fn select_all(&mut self, ids: &[u32]) {
+        self.anchor = None;
+        self.ids = ids.iter().copied().collect();
+    }
+
+    /// Forget songs which aren't in the queue anymore, after it has changed.
+    pub fn retain(&mut self, ids: &[u32]) {
+        let queued: HashSet<u32> = ids.iter().copied().collect();
+        self.ids.retain(|id| queued.contains(id));
+        self.anchor = self.anchor.filter(|anchor| queued.contains(anchor));
+    }
+
+    pub fn invert(&mut self, ids: &[u32], cursor: usize) {
+        self.commit_visual(ids, cursor);
+        self.ids = ids.iter().copied().filter(|id| !self.ids.contains(id)).collect();
+    }
+
+    /// Indices of the visual range, if it's started and its song is still in the queue.
+    pub fn visual_range(&self, ids: &[u32], cursor: usize) -> Option<RangeInclusive<usize>> {
+        let anchor = self.anchor?;
+        let start = ids.iter().position(|&id| id == anchor)?;
+        Some(start.min(cursor)..=start.max(cursor).min(ids.len().saturating_sub(1)))
+    }
+
+    /// Indices of every selected song in the queue order, including the visual range, or
+    /// the index of the song at the `cursor` if nothing is selected.
+    pub fn targets(&self, ids: &[u32], cursor: usize) -> Vec<usize> {
+        let range = self.visual_range(ids, cursor);
+        let selected: Vec<usize> = (0..ids.len())
+            .filter(|index| {
+                self.ids.contains(&ids[*index])
+                    || range.as_ref().is_some_and(|range| range.contains(index))
+            })
+            .collect();
+        if selected.is_empty() && cursor < ids.len() {
+            return vec![cursor];
+        }
+        selected
+    }
+
+    fn commit_visual(&mut self, ids: &[u32], cursor: usize) {
+        if let Some(range) = self.visual_range(ids, cursor) {
+            self.ids.extend(&ids[range]);
+        }
+        self.anchor = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Selection;
+
+    #[test]
+    fn visual_range_follows_its_song() {
+        let mut selection = Selection::default();
+        selection.toggle_visual(&[10, 11, 12, 13, 14], 1);
+        assert!(selection.is_visual());
+        assert_eq!(selection.visual_range(&[10, 11, 12, 13, 14], 3), Some(1..=3));
+        assert_eq!(selection.visual_range(&[10, 11, 12, 13, 14], 0), Some(0..=1));
+
+        // Song of the anchor is moved by another client, and the range starts from it.
+        assert_eq!(selection.visual_range(&[11, 10, 12, 13, 14], 3), Some(0..=3));
+        // Queue is shortened below the cursor.
+        assert_eq!(selection.visual_range(&[10, 11, 12], 4), Some(1..=2));
+        // Song of the anchor is removed.
+        assert_eq!(selection.visual_range(&[10, 12, 13], 2), None);
+
+        selection.toggle_visual(&[10, 11, 12, 13, 14], 2);
+        assert!(!selection.is_visual());
+        assert_eq!(selection.len(), 2);
+        assert!(selection.contains(11) && selection.contains(12));
+    }
+
+    #[test]
+    fn inverts_with_visual_range() {
+        let ids = [10, 11, 12, 13, 14];
+        let mut selection = Selection::default();
+        selection.toggle(10);
+        selection.toggle_visual(&ids, 3);
+        selection.invert(&ids, 4);
+        assert!(!selection.is_visual());
+        assert_eq!(selection.targets(&ids, 0), [1, 2]);
+
+        selection.invert(&ids, 0);
+        assert_eq!(selection.targets(&ids, 0), [0, 3, 4]);
+        selection.select_all(&ids);
+        selection.invert(&ids, 0);
+        assert_eq!(selection.len(), 0);
+    }
+
+    #[test]
+    fn targets_selection_or_cursor() {
+        let ids = [10, 11, 12, 13, 14];
+        let mut selection = Selection::default();
+        assert_eq!(selection.targets(&ids, 2), [2]);
+        assert_eq!(selection.targets(&ids, 5), Vec::<usize>::new());
+        assert_eq!(selection.targets(&[], 0), Vec::<usize>::new());
+
+        // Selected songs and the visual range, in the queue order without the cursor.
+        selection.toggle(14);
+        selection.toggle(10);
+        selection.toggle_visual(&ids, 2);
+        assert_eq!(selection.targets(&ids, 1), [0, 1, 2, 4]);
+        assert_eq!(selection.targets(&[14, 13, 12, 11, 10], 2), [0, 2, 4]);
+
+        assert!(selection.cancel_visual());
+        assert!(!selection.cancel_visual());
+        assert_eq!(selection.targets(&ids, 1), [0, 4]);
+        selection.clear();
+        assert_eq!(selection.targets(&ids, 1), [1]);
+    }
+
+    #[test]
+    fn forgets_removed_songs() {
+        let mut selection = Selection::default();
+        selection.toggle(10);
+        selection.toggle(12);
+        selection.toggle_visual(&[10, 11, 12, 13], 2);
+
+        // Removed songs aren't targeted even before the selection is updated.
+        let ids = [10, 11, 13];
+        assert_eq!(selection.targets(&ids, 1), [0]);
+        selection.retain(&ids);
+        assert_eq!(selection.len(), 1);
+        assert!(!selection.contains(12));
+        assert!(!selection.is_visual());
+
+        // Removed songs added again get new ids, so they stay unselected.
+        assert_eq!(selection.targets(&[10, 11, 13, 15], 3), [0]);
+    }
+}
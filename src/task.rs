@@ -1,3 +1,5 @@
+use std::future::Future;
+
 use iocraft::prelude::*;
 
 #[derive(Debug)]
@@ -22,18 +24,18 @@ pub enum TaskStatus<T: Unpin + Send + Sync + 'static> {
 pub trait UseTask<T: Unpin + Send + Sync + 'static> {
     fn use_task<F>(&mut self, f: F) -> Task<T>
     where
-        F: FnOnce() -> eyre::Result<T> + Send + 'static;
+        F: Future<Output = eyre::Result<T>> + Send + 'static;
 }
 
 impl<'a, T: Unpin + Send + Sync + 'static> UseTask<T> for Hooks<'_, '_> {
     fn use_task<F>(&mut self, f: F) -> Task<T>
     where
-        F: FnOnce() -> eyre::Result<T> + Send + 'static,
+        F: Future<Output = eyre::Result<T>> + Send + 'static,
     {
         let mut state = self.use_state(|| TaskStatus::<T>::InProgress);
 
         self.use_future(async move {
-            let result = smol::unblock(move || f()).await;
+            let result = f.await;
             state.set(match result {
                 Ok(done) => TaskStatus::Done(done),
                 Err(error) => TaskStatus::Error(error),
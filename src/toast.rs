@@ -0,0 +1,97 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use event_listener::Event;
+use iocraft::prelude::*;
+
+use crate::{app::AppContext, mpd::MpdError};
+
+/// How long a toast stays visible.
+const TOAST_LIFETIME: Duration = Duration::from_secs(5);
+/// How many toasts are visible at once, older ones are dropped first.
+const MAX_TOASTS: usize = 3;
+
+/// Short error message in the [`ToastArea`].
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub message: String,
+    shown_at: Instant,
+}
+
+/// Shared list of toasts, shown by [`ToastArea`] for a few seconds each.
+#[derive(Debug, Clone, Default)]
+pub struct Toasts {
+    toasts: Arc<Mutex<Vec<Toast>>>,
+    event: Arc<Event>,
+}
+
+impl Toasts {
+    pub fn error(&self, message: impl Into<String>) {
+        {
+            let mut toasts = self.toasts.lock().unwrap();
+            toasts.push(Toast { message: message.into(), shown_at: Instant::now() });
+            let overflow = toasts.len().saturating_sub(MAX_TOASTS);
+            toasts.drain(..overflow);
+        }
+        self.event.notify(usize::MAX);
+    }
+
+    /// Show a failed MPD request. Broken connection errors are skipped, since the
+    /// connection state is already shown by the status bar, but timeouts and broken
+    /// responses are shown to explain why the connection is being restored.
+    pub fn mpd_error(&self, error: &MpdError) {
+        let is_explained = matches!(error, MpdError::Timeout { .. } | MpdError::Protocol(_));
+        if error.is_recoverable() || is_explained {
+            self.error(error.to_string());
+        }
+    }
+
+    /// Drop expired toasts, and get the rest of them.
+    fn visible(&self) -> Vec<Toast> {
+        let mut toasts = self.toasts.lock().unwrap();
+        toasts.retain(|toast| toast.shown_at.elapsed() < TOAST_LIFETIME);
+        toasts.clone()
+    }
+}
+
+/// Notification area with recent [`Toasts`] from [`AppContext`].
+#[component]
+pub fn ToastArea(mut hooks: Hooks) -> impl Into<AnyElement<'static>> {
+    let ctx = hooks.use_context::<AppContext>();
+
+    let mut visible: State<Vec<Toast>> = hooks.use_state_default();
+    let toasts = ctx.toasts.clone();
+    hooks.use_future(async move {
+        loop {
+            let listener = toasts.event.listen();
+            let current = toasts.visible();
+            let has_toasts = !current.is_empty();
+            visible.set(current);
+
+            if has_toasts {
+                // Wake up periodically to hide expired toasts.
+                smol::future::or(listener, async {
+                    smol::Timer::after(Duration::from_secs(1)).await;
+                })
+                .await;
+            } else {
+                listener.await;
+            }
+        }
+    });
+
+    element! {
+        View(
+            width: Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            padding_left: 1,
+            padding_right: 1,
+        ) {
+            #(visible.read().iter().map(|toast| element! {
+                Text(color: Color::Red, content: toast.message.clone())
+            }))
+        }
+    }
+}